@@ -1,22 +1,54 @@
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 
-#[derive(Clone, PartialEq, Debug)]
+use crate::randomizer::{Randomizer, PIECES};
+
+// ChaCha8 gives the same sequence for a seed on every platform, including wasm
+#[derive(Clone, Debug)]
 pub struct Bag {
     pieces: Vec<char>,
+    copies: usize,
+    rng: ChaCha8Rng,
+    seed: u64,
 }
 
 impl Bag {
-    pub fn new() -> Self {
-        let mut pieces = vec!['I', 'J', 'L', 'O', 'S', 'T', 'Z'];
-        pieces.shuffle(&mut thread_rng());
-        Bag { pieces }
+    pub fn new(seed: u64) -> Self {
+        Bag::with_copies(seed, 1)
+    }
+
+    // a bag holding `copies` of every piece, e.g. 2 for a 14-bag
+    pub fn with_copies(seed: u64, copies: usize) -> Self {
+        let mut bag = Bag {
+            pieces: Vec::new(),
+            copies,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            seed,
+        };
+        bag.refill();
+        bag
+    }
+
+    fn refill(&mut self) {
+        self.pieces = PIECES.repeat(self.copies);
+        self.pieces.shuffle(&mut self.rng);
     }
+}
 
-    pub fn draw(&mut self) -> char {
+impl Randomizer for Bag {
+    fn draw(&mut self) -> char {
         if self.pieces.is_empty() {
-            *self = Bag::new();
+            self.refill();
         }
         self.pieces.pop().unwrap()
     }
+
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn box_clone(&self) -> Box<dyn Randomizer> {
+        Box::new(self.clone())
+    }
 }
@@ -0,0 +1,228 @@
+use crate::tetrominoe::TColor;
+
+// locked cells as one bitmask per row, top row first, with bit n set when column n
+// is filled, plus the color of every cell for drawing
+#[derive(Clone, Debug)]
+pub struct Board {
+    width: usize,
+    rows: Vec<u16>,
+    colors: Vec<TColor>,
+}
+
+impl Board {
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width <= 16, "boards are at most 16 columns wide");
+        Board {
+            width,
+            rows: vec![0; height],
+            colors: vec![TColor::Empty; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn full(&self) -> u16 {
+        ((1u32 << self.width) - 1) as u16
+    }
+
+    // anything outside the board counts as filled
+    pub fn filled(&self, row: i32, col: i32) -> bool {
+        row < 0
+            || col < 0
+            || row as usize >= self.rows.len()
+            || col as usize >= self.width
+            || self.rows[row as usize] & (1 << col) != 0
+    }
+
+    pub fn color(&self, row: usize, col: usize) -> TColor {
+        self.colors[row * self.width + col]
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, color: TColor) {
+        self.rows[row] |= 1 << col;
+        self.colors[row * self.width + col] = color;
+    }
+
+    // whether rows of cells, with bit n for the box column n, fit with the box's
+    // top left corner at this position
+    pub fn fits(&self, masks: &[u16; 4], row: i32, col: i32) -> bool {
+        let full = self.full() as u32;
+        masks.iter().enumerate().all(|(i, &mask)| {
+            if mask == 0 {
+                return true;
+            }
+
+            let r = row + i as i32;
+            if r < 0 || r as usize >= self.rows.len() {
+                return false;
+            }
+
+            let mask = mask as u32;
+            let shifted = if col < 0 {
+                if mask & ((1 << -col) - 1) != 0 {
+                    return false;
+                }
+                mask >> -col
+            } else {
+                mask << col
+            };
+            shifted & !full == 0 && shifted & self.rows[r as usize] as u32 == 0
+        })
+    }
+
+    // removes full rows and returns how many there were
+    pub fn clear_lines(&mut self) -> usize {
+        let full = self.full();
+        let width = self.width;
+        let mut kept = self.rows.len();
+        for row in (0..self.rows.len()).rev() {
+            if self.rows[row] == full {
+                continue;
+            }
+            kept -= 1;
+            self.rows[kept] = self.rows[row];
+            self.colors
+                .copy_within(row * width..(row + 1) * width, kept * width);
+        }
+
+        // everything above the kept rows is now empty
+        let lines = kept;
+        self.rows[..lines].fill(0);
+        self.colors[..lines * width].fill(TColor::Empty);
+        lines
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|&row| row == 0)
+    }
+
+    // pushes every row up one and adds a filled bottom row with a hole,
+    // returns false without changing anything if the top row is not empty
+    pub fn push_garbage(&mut self, hole: usize, color: TColor) -> bool {
+        assert!(hole < self.width, "garbage hole outside the board");
+        if self.rows[0] != 0 {
+            return false;
+        }
+
+        let width = self.width;
+        let bottom = self.rows.len() - 1;
+        self.rows.copy_within(1.., 0);
+        self.colors.copy_within(width.., 0);
+        self.rows[bottom] = self.full() & !(1 << hole);
+        for col in 0..width {
+            self.colors[bottom * width + col] = if col == hole { TColor::Empty } else { color };
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a flat T, bit n for column n of its box
+    const T: [u16; 4] = [0b010, 0b111, 0, 0];
+
+    #[test]
+    fn outside_counts_as_filled() {
+        let board = Board::new(10, 4);
+        assert!(!board.filled(0, 0));
+        assert!(!board.filled(3, 9));
+        assert!(board.filled(-1, 0));
+        assert!(board.filled(0, -1));
+        assert!(board.filled(4, 0));
+        assert!(board.filled(0, 10));
+    }
+
+    #[test]
+    fn fits_inside_walls_floor_and_top() {
+        let board = Board::new(10, 4);
+        assert!(board.fits(&T, 0, 0));
+        assert!(board.fits(&T, 0, 7));
+        assert!(!board.fits(&T, 0, -1));
+        assert!(!board.fits(&T, 0, 8));
+        assert!(board.fits(&T, 2, 0));
+        assert!(!board.fits(&T, 3, 0));
+        assert!(!board.fits(&T, -1, 0));
+
+        // empty rows and columns of the box may hang off the board
+        let lowered = [0, 0b010, 0b111, 0];
+        assert!(board.fits(&lowered, -1, 0));
+        let shifted = [0b100, 0b110, 0b100, 0];
+        assert!(board.fits(&shifted, 0, -1));
+        assert!(!board.fits(&shifted, 0, -2));
+    }
+
+    #[test]
+    fn fits_around_filled_cells() {
+        let mut board = Board::new(10, 4);
+        board.set(3, 4, TColor::Gray);
+        assert!(!board.fits(&T, 2, 3));
+        assert!(board.fits(&T, 1, 3));
+        assert!(board.fits(&T, 2, 5));
+    }
+
+    #[test]
+    fn clearing_keeps_colors_with_their_rows() {
+        let mut board = Board::new(4, 5);
+        board.set(1, 0, TColor::Red);
+        for col in 0..4 {
+            board.set(2, col, TColor::Gray);
+            board.set(4, col, TColor::Gray);
+        }
+        board.set(3, 3, TColor::Blue);
+
+        assert_eq!(board.clear_lines(), 2);
+        for row in 0..3 {
+            for col in 0..4 {
+                assert!(!board.filled(row, col));
+            }
+        }
+        assert!(board.filled(3, 0));
+        assert_eq!(board.color(3, 0), TColor::Red);
+        assert!(board.filled(4, 3));
+        assert_eq!(board.color(4, 3), TColor::Blue);
+        assert!(!board.filled(4, 0));
+        assert_eq!(board.color(4, 0), TColor::Empty);
+        assert_eq!(board.color(2, 1), TColor::Empty);
+    }
+
+    #[test]
+    fn garbage_pushes_the_stack_up() {
+        let mut board = Board::new(4, 3);
+        board.set(2, 1, TColor::Red);
+
+        assert!(board.push_garbage(2, TColor::Gray));
+        assert!(board.filled(1, 1));
+        assert_eq!(board.color(1, 1), TColor::Red);
+        assert!(!board.filled(2, 2));
+        assert_eq!(board.color(2, 0), TColor::Gray);
+        assert_eq!(board.clear_lines(), 0);
+    }
+
+    #[test]
+    fn garbage_overflows_at_the_top() {
+        let mut board = Board::new(4, 3);
+        board.set(0, 3, TColor::Red);
+        board.set(2, 0, TColor::Blue);
+
+        assert!(!board.push_garbage(0, TColor::Gray));
+        // nothing moved
+        assert!(board.filled(0, 3));
+        assert!(board.filled(2, 0));
+        assert!(!board.filled(2, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "garbage hole outside the board")]
+    fn garbage_hole_must_be_on_the_board() {
+        let mut board = Board::new(4, 3);
+        board.push_garbage(4, TColor::Gray);
+    }
+}
@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use crate::{randomizer::RandomizerKind, scoring::ScoringKind};
+
+#[derive(Clone)]
+pub struct GameConfig {
+    pub mode: GameMode,
+    pub lock_delay: Duration,
+    pub lock_resets: u32,
+    pub gravity: GravityCurve,
+    // number of upcoming pieces shown, from 1 to 6
+    pub preview: usize,
+    pub randomizer: RandomizerKind,
+    pub start_level: u32,
+    pub level_up: LevelUp,
+    pub scoring: ScoringKind,
+    // rows above the visible playfield that pieces spawn into and the stack can grow into
+    pub hidden_rows: usize,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameMode::Marathon.config()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GameMode {
+    Marathon,
+    Classic,
+    Master,
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 3] = [GameMode::Marathon, GameMode::Classic, GameMode::Master];
+
+    pub fn name(&self) -> &str {
+        match self {
+            GameMode::Marathon => "MARATHON",
+            GameMode::Classic => "CLASSIC",
+            GameMode::Master => "MASTER",
+        }
+    }
+
+    pub fn config(&self) -> GameConfig {
+        match self {
+            GameMode::Marathon => GameConfig {
+                mode: *self,
+                lock_delay: Duration::from_millis(500),
+                lock_resets: 15,
+                gravity: GravityCurve::Guideline,
+                preview: 5,
+                randomizer: RandomizerKind::SevenBag,
+                start_level: 0,
+                level_up: LevelUp::Lines(10),
+                scoring: ScoringKind::Guideline,
+                hidden_rows: 20,
+            },
+            // no lock delay, a single preview and pieces spawning inside the matrix, like the NES
+            GameMode::Classic => GameConfig {
+                mode: *self,
+                lock_delay: Duration::ZERO,
+                lock_resets: 0,
+                gravity: GravityCurve::nes(),
+                preview: 1,
+                randomizer: RandomizerKind::Nes,
+                start_level: 0,
+                level_up: LevelUp::Lines(10),
+                scoring: ScoringKind::Nes,
+                hidden_rows: 0,
+            },
+            GameMode::Master => GameConfig {
+                mode: *self,
+                lock_delay: Duration::from_millis(500),
+                lock_resets: 15,
+                gravity: GravityCurve::Guideline,
+                preview: 1,
+                randomizer: RandomizerKind::Tgm,
+                start_level: 0,
+                level_up: LevelUp::Lines(10),
+                scoring: ScoringKind::Tgm,
+                hidden_rows: 20,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LevelUp {
+    // one level per this many lines cleared
+    Lines(u32),
+    // one level per this many points, how levels originally worked here
+    Score(u32),
+}
+
+#[derive(Clone)]
+pub enum GravityCurve {
+    // (0.8 - (level - 1) * 0.007) ^ (level - 1) seconds per row, for guideline levels 1 to 20
+    Guideline,
+    // seconds per row for each level, the last entry is used for every level after it
+    Table(Vec<f32>),
+}
+
+impl GravityCurve {
+    // NTSC frames per row for levels 0 to 29
+    pub fn nes() -> Self {
+        let frames = [
+            48, 43, 38, 33, 28, 23, 18, 13, 8, 6, 5, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 2, 2, 2, 2,
+            2, 2, 2, 1,
+        ];
+        GravityCurve::Table(frames.iter().map(|&f| f as f32 / 60.0988).collect())
+    }
+
+    pub fn interval(&self, level: u32) -> Duration {
+        match self {
+            GravityCurve::Guideline => {
+                // our levels start at 0, the guideline's at 1
+                let n = level.min(19) as f32;
+                Duration::from_secs_f32((0.8 - n * 0.007).powf(n))
+            }
+            // entries that are not a usable number of seconds fall back to the closest
+            // valid one before them, or to the guideline curve when there is none
+            GravityCurve::Table(table) => table
+                .iter()
+                .take(level as usize + 1)
+                .rev()
+                .find_map(|&seconds| Duration::try_from_secs_f32(seconds).ok())
+                .unwrap_or_else(|| GravityCurve::Guideline.interval(level)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_uses_last_entry_past_its_end() {
+        let table = GravityCurve::Table(vec![1.0, 0.5]);
+        assert_eq!(table.interval(0), Duration::from_secs(1));
+        assert_eq!(table.interval(1), Duration::from_millis(500));
+        assert_eq!(table.interval(30), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn invalid_table_entries_fall_back() {
+        let table = GravityCurve::Table(vec![1.0, f32::NAN, -1.0, f32::INFINITY]);
+        assert_eq!(table.interval(3), Duration::from_secs(1));
+
+        let empty = GravityCurve::Table(Vec::new());
+        assert_eq!(empty.interval(5), GravityCurve::Guideline.interval(5));
+
+        let invalid = GravityCurve::Table(vec![f32::NAN]);
+        assert_eq!(invalid.interval(0), GravityCurve::Guideline.interval(0));
+    }
+}
@@ -1,11 +1,307 @@
+use crate::{
+    gameconfig::{GameConfig, LevelUp},
+    scoring::ScoringRules,
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+// what a single lock achieved, kept for the UI to announce
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Clear {
+    pub lines: usize,
+    pub spin: Spin,
+    // nothing left on the board afterwards
+    pub perfect: bool,
+    // filled in when scored
+    pub combo: u32,
+    pub back_to_back: bool,
+}
+
+impl Clear {
+    pub fn new(lines: usize, spin: Spin, perfect: bool) -> Self {
+        Clear {
+            lines,
+            spin,
+            perfect,
+            combo: 0,
+            back_to_back: false,
+        }
+    }
+
+    // tetrises and line clearing T-spins keep back-to-back going
+    pub fn is_difficult(&self) -> bool {
+        self.lines == 4 || (self.lines > 0 && self.spin != Spin::None)
+    }
+
+    pub fn name(&self) -> String {
+        let lines = match self.lines {
+            0 => "",
+            1 => "SINGLE",
+            2 => "DOUBLE",
+            3 => "TRIPLE",
+            _ => "TETRIS",
+        };
+
+        let mut name = match self.spin {
+            Spin::None => lines.to_string(),
+            Spin::Mini => format!("T-SPIN MINI\n{}", lines),
+            Spin::Full => format!("T-SPIN\n{}", lines),
+        };
+
+        if self.back_to_back {
+            name = format!("B2B {}", name);
+        }
+        if self.perfect {
+            name = format!("PERFECT CLEAR\n{}", name);
+        }
+        if self.combo > 1 {
+            name = format!("{}\n{} COMBO", name, self.combo - 1);
+        }
+        name
+    }
+}
+
 #[derive(Clone)]
 pub struct GameScore {
     pub score: u32,
     pub level: u32,
+    pub lines: u32,
+    pub start_level: u32,
+    pub level_up: LevelUp,
+    // consecutive locks that cleared lines, and consecutive difficult clears
+    pub combo: u32,
+    pub back_to_back: u32,
+    pub last_clear: Option<Clear>,
+    pub rules: Box<dyn ScoringRules>,
 }
 
 impl GameScore {
-    pub fn new() -> Self {
-        GameScore { score: 0, level: 0 }
+    pub fn new(config: &GameConfig) -> Self {
+        GameScore {
+            score: 0,
+            level: config.start_level,
+            lines: 0,
+            start_level: config.start_level,
+            level_up: config.level_up,
+            rules: config.scoring.build(),
+            combo: 0,
+            back_to_back: 0,
+            last_clear: None,
+        }
+    }
+
+    pub fn add_drop(&mut self, rows: u32, hard: bool) {
+        self.score += self.rules.drop_points(rows, hard);
+        self.update_level();
+    }
+
+    pub fn add_clear(&mut self, mut clear: Clear) {
+        // zero line T-spins neither start nor break back-to-back
+        if clear.is_difficult() {
+            clear.back_to_back = self.back_to_back > 0;
+            self.back_to_back += 1;
+        } else if clear.lines > 0 {
+            self.back_to_back = 0;
+        }
+
+        if clear.lines > 0 {
+            self.combo += 1;
+        } else {
+            self.combo = 0;
+        }
+        clear.combo = self.combo;
+
+        self.score += self.rules.clear_points(&clear, self.level);
+
+        if clear.lines > 0 || clear.spin != Spin::None {
+            self.last_clear = Some(clear);
+        }
+
+        self.lines += clear.lines as u32;
+        self.update_level();
+    }
+
+    pub fn grade(&self) -> Option<&'static str> {
+        self.rules.grade(self.score)
+    }
+
+    // a step of 0 never levels up
+    fn update_level(&mut self) {
+        self.level = self.start_level
+            + match self.level_up {
+                LevelUp::Lines(lines) => self.lines.checked_div(lines),
+                LevelUp::Score(points) => self.score.checked_div(points),
+            }
+            .unwrap_or(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scoring::ScoringKind;
+
+    fn score(level_up: LevelUp) -> GameScore {
+        GameScore::new(&GameConfig {
+            start_level: 3,
+            level_up,
+            ..GameConfig::default()
+        })
+    }
+
+    #[test]
+    fn levels_up_every_ten_lines() {
+        let mut gamescore = score(LevelUp::Lines(10));
+        for _ in 0..3 {
+            gamescore.add_clear(Clear::new(4, Spin::None, false));
+        }
+        assert_eq!(gamescore.lines, 12);
+        assert_eq!(gamescore.level, 4);
+    }
+
+    #[test]
+    fn zero_step_keeps_start_level() {
+        let mut gamescore = score(LevelUp::Lines(0));
+        gamescore.add_clear(Clear::new(4, Spin::None, false));
+        assert_eq!(gamescore.level, 3);
+
+        let mut gamescore = score(LevelUp::Score(0));
+        gamescore.add_drop(10, true);
+        gamescore.add_clear(Clear::new(1, Spin::None, false));
+        assert_eq!(gamescore.level, 3);
+    }
+
+    // level 0 throughout, so points are not multiplied
+    fn rules(scoring: ScoringKind) -> GameScore {
+        GameScore::new(&GameConfig {
+            start_level: 0,
+            level_up: LevelUp::Lines(100),
+            scoring,
+            ..GameConfig::default()
+        })
+    }
+
+    fn clear(gamescore: &mut GameScore, lines: usize, spin: Spin) -> u32 {
+        let before = gamescore.score;
+        gamescore.add_clear(Clear::new(lines, spin, false));
+        gamescore.score - before
+    }
+
+    #[test]
+    fn combo_adds_fifty_per_clear_after_the_first() {
+        let mut gamescore = rules(ScoringKind::Guideline);
+        assert_eq!(clear(&mut gamescore, 1, Spin::None), 100);
+        assert_eq!(clear(&mut gamescore, 1, Spin::None), 150);
+        assert_eq!(clear(&mut gamescore, 2, Spin::None), 400);
+        assert_eq!(gamescore.combo, 3);
+
+        // a lock without lines ends the combo
+        assert_eq!(clear(&mut gamescore, 0, Spin::None), 0);
+        assert_eq!(gamescore.combo, 0);
+        assert_eq!(clear(&mut gamescore, 1, Spin::None), 100);
+    }
+
+    #[test]
+    fn back_to_back_multiplies_by_one_and_a_half() {
+        let mut gamescore = rules(ScoringKind::Guideline);
+        assert_eq!(clear(&mut gamescore, 4, Spin::None), 800);
+        clear(&mut gamescore, 0, Spin::None);
+        assert_eq!(clear(&mut gamescore, 2, Spin::Full), 1800);
+        assert_eq!(gamescore.back_to_back, 2);
+        assert!(gamescore.last_clear.unwrap().back_to_back);
+
+        // anything else that clears lines breaks it
+        clear(&mut gamescore, 0, Spin::None);
+        assert_eq!(clear(&mut gamescore, 3, Spin::None), 500);
+        assert_eq!(gamescore.back_to_back, 0);
+        clear(&mut gamescore, 0, Spin::None);
+        assert_eq!(clear(&mut gamescore, 4, Spin::None), 800);
+    }
+
+    #[test]
+    fn zero_line_spins_neither_start_nor_break_back_to_back() {
+        let mut gamescore = rules(ScoringKind::Guideline);
+        assert_eq!(clear(&mut gamescore, 0, Spin::Full), 400);
+        assert_eq!(gamescore.back_to_back, 0);
+        assert_eq!(clear(&mut gamescore, 4, Spin::None), 800);
+
+        clear(&mut gamescore, 0, Spin::None);
+        assert_eq!(clear(&mut gamescore, 0, Spin::Mini), 100);
+        assert_eq!(gamescore.back_to_back, 1);
+        assert_eq!(clear(&mut gamescore, 4, Spin::None), 1200);
+    }
+
+    #[test]
+    fn perfect_clear_bonus_per_line_count() {
+        let mut gamescore = rules(ScoringKind::Guideline);
+        gamescore.add_clear(Clear::new(1, Spin::None, true));
+        assert_eq!(gamescore.score, 100 + 800);
+        assert!(gamescore.last_clear.unwrap().perfect);
+
+        let mut gamescore = rules(ScoringKind::Guideline);
+        gamescore.add_clear(Clear::new(4, Spin::None, true));
+        assert_eq!(gamescore.score, 800 + 2000);
+    }
+
+    #[test]
+    fn back_to_back_perfect_tetris() {
+        let mut gamescore = rules(ScoringKind::Guideline);
+        clear(&mut gamescore, 4, Spin::None);
+        clear(&mut gamescore, 0, Spin::None);
+
+        let before = gamescore.score;
+        gamescore.add_clear(Clear::new(4, Spin::None, true));
+        assert_eq!(gamescore.score - before, 1200 + 3200);
+        assert!(gamescore.last_clear.unwrap().back_to_back);
+    }
+
+    #[test]
+    fn nes_scores_soft_drops_and_lines_only() {
+        let mut gamescore = rules(ScoringKind::Nes);
+        gamescore.add_drop(5, false);
+        gamescore.add_drop(10, true);
+        assert_eq!(gamescore.score, 5);
+
+        assert_eq!(clear(&mut gamescore, 4, Spin::None), 1200);
+        assert_eq!(clear(&mut gamescore, 4, Spin::None), 1200);
+        assert_eq!(clear(&mut gamescore, 1, Spin::Full), 40);
+        assert_eq!(gamescore.grade(), None);
+    }
+
+    #[test]
+    fn tgm_combo_and_bravo() {
+        let mut gamescore = rules(ScoringKind::Tgm);
+        gamescore.add_drop(10, true);
+        gamescore.add_drop(3, false);
+        assert_eq!(gamescore.score, 0);
+
+        // (ceil((0 + 2) / 4) + 3 soft) * 2 lines * combo 3
+        assert_eq!(clear(&mut gamescore, 2, Spin::None), 24);
+        // the combo carries on, the soft drop does not
+        assert_eq!(clear(&mut gamescore, 1, Spin::None), 3);
+        assert_eq!(clear(&mut gamescore, 0, Spin::None), 0);
+
+        // combo 7 and bravo 4
+        gamescore.add_clear(Clear::new(4, Spin::None, true));
+        assert_eq!(gamescore.score, 24 + 3 + 4 * 7 * 4);
+    }
+
+    #[test]
+    fn tgm_grades_from_score() {
+        let mut gamescore = rules(ScoringKind::Tgm);
+        assert_eq!(gamescore.grade(), Some("9"));
+        gamescore.score = 399;
+        assert_eq!(gamescore.grade(), Some("9"));
+        gamescore.score = 400;
+        assert_eq!(gamescore.grade(), Some("8"));
+        gamescore.score = 16000;
+        assert_eq!(gamescore.grade(), Some("S1"));
+        gamescore.score = 200000;
+        assert_eq!(gamescore.grade(), Some("S9"));
     }
 }
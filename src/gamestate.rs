@@ -1,9 +1,14 @@
+use std::{collections::VecDeque, time::Duration};
+
 use crate::{
-    bag::Bag,
+    board::Board,
+    gameconfig::GameConfig,
     gamescore::GameScore,
-    tetlib::{init, new_piece},
-    tetrominoe::Tetrominoe,
+    randomizer::Randomizer,
+    tetlib::{ghost_piece, gravity, handle_input, lock_delay, new_piece, Action, ActionResult},
+    tetrominoe::{Piece, Rotation, Tetrominoe},
 };
+#[cfg(feature = "bevy")]
 use bevy::prelude::Resource;
 
 // impl GameWrapper {
@@ -15,33 +20,145 @@ use bevy::prelude::Resource;
 //     }
 // }
 
-#[derive(Clone, Resource)]
+#[derive(Clone)]
+#[cfg_attr(feature = "bevy", derive(Resource))]
 pub struct GameState {
-    pub display: Vec<Vec<Tetrominoe>>,
-    pub active_piece: Tetrominoe,
+    pub board: Board,
+    pub active_piece: Piece,
     pub hold_piece: Option<Tetrominoe>,
-    pub next_piece: Tetrominoe,
+    pub hold_used: bool,
+    next_queue: VecDeque<Tetrominoe>,
+    // pieces locked so far
     pub counter: usize,
-    pub is_game_over: bool,
-    pub bag: Bag,
+    pub top_out: Option<TopOut>,
+    pub randomizer: Box<dyn Randomizer>,
     pub gamescore: GameScore,
+    pub config: GameConfig,
+    // time since the active piece last fell a row
+    pub gravity_timer: Duration,
+    pub lock_timer: Duration,
+    pub lock_resets: u32,
+    pub lowest_row: i32,
+    // rotation and kick index of the last successful move, if it was a rotation
+    pub last_rotation: Option<(Rotation, usize)>,
+}
+
+// the rule that ended the game
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TopOut {
+    // a new piece spawned overlapping the stack
+    Block,
+    // a piece locked entirely above the visible playfield
+    Lock,
+    // garbage pushed the stack past the top of the hidden rows
+    Garbage,
+}
+
+impl TopOut {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TopOut::Block => "BLOCK OUT",
+            TopOut::Lock => "LOCK OUT",
+            TopOut::Garbage => "GARBAGE OUT",
+        }
+    }
 }
 
 impl GameState {
-    pub fn new(width: usize, height: usize) -> Self {
+    pub fn new(width: usize, height: usize, seed: u64) -> Self {
+        GameState::with_config(width, height, seed, GameConfig::default())
+    }
+
+    pub fn with_config(width: usize, height: usize, seed: u64, config: GameConfig) -> Self {
         let mut gs = GameState {
-            display: init(width, height),
-            active_piece: Tetrominoe::new(None, None),
+            board: Board::new(width, height + config.hidden_rows),
+            active_piece: Piece::new(' ', 0, 0),
             hold_piece: None,
-            next_piece: Tetrominoe::random(&mut Bag::new()),
+            hold_used: false,
+            next_queue: VecDeque::new(),
             counter: 0,
-            is_game_over: false,
-            bag: Bag::new(),
-            gamescore: GameScore::new(),
+            top_out: None,
+            randomizer: config.randomizer.build(seed),
+            gamescore: GameScore::new(&config),
+            config,
+            gravity_timer: Duration::ZERO,
+            lock_timer: Duration::ZERO,
+            lock_resets: 0,
+            lowest_row: 0,
+            last_rotation: None,
         };
+
+        for _ in 0..gs.config.preview.clamp(1, 6) {
+            let piece = Tetrominoe::random(gs.randomizer.as_mut());
+            gs.next_queue.push_back(piece);
+        }
         new_piece(&mut gs, None);
         gs
     }
+
+    // pieces spawn in the two rows just above the visible playfield, or at the very top
+    // if there are fewer hidden rows than that
+    pub fn spawn_row(&self) -> usize {
+        self.config.hidden_rows.saturating_sub(2)
+    }
+
+    pub fn apply(&mut self, action: Action) -> ActionResult {
+        handle_input(self, action)
+    }
+
+    // advance the game by `delta`, letting the active piece fall and lock,
+    // returns true on game over
+    pub fn tick(&mut self, delta: Duration) -> bool {
+        // at high levels several rows can fall in one tick, and with no interval at
+        // all (20G) the piece drops straight to the floor
+        let interval = self.config.gravity.interval(self.gamescore.level);
+        if interval.is_zero() {
+            while gravity(self) {}
+        } else {
+            self.gravity_timer += delta;
+            while self.gravity_timer >= interval {
+                self.gravity_timer -= interval;
+                if !gravity(self) {
+                    self.gravity_timer = Duration::ZERO;
+                    break;
+                }
+            }
+        }
+
+        lock_delay(self, delta);
+        self.is_game_over()
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn active_piece(&self) -> Piece {
+        self.active_piece
+    }
+
+    pub fn ghost_piece(&self) -> Piece {
+        ghost_piece(self)
+    }
+
+    pub fn is_game_over(&self) -> bool {
+        self.top_out.is_some()
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.randomizer.seed()
+    }
+
+    // upcoming pieces, the one that spawns next first
+    pub fn next_pieces(&self) -> impl Iterator<Item = &Tetrominoe> {
+        self.next_queue.iter()
+    }
+
+    pub fn pop_next_piece(&mut self) -> char {
+        let piece = Tetrominoe::random(self.randomizer.as_mut());
+        self.next_queue.push_back(piece);
+        self.next_queue.pop_front().unwrap().ptype
+    }
 }
 
 // return true if user wants to repeat
@@ -143,3 +260,29 @@ impl GameState {
 //         buf.trim().to_string()
 //     }};
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gameconfig::GravityCurve, tetlib::grounded};
+
+    #[test]
+    fn zero_interval_drops_to_the_floor() {
+        let config = GameConfig {
+            gravity: GravityCurve::Table(vec![0.0]),
+            ..GameConfig::default()
+        };
+        let mut gs = GameState::with_config(10, 20, 0, config);
+        assert!(!gs.tick(Duration::from_millis(1)));
+        assert!(grounded(&gs));
+    }
+
+    #[test]
+    fn long_tick_stops_once_grounded() {
+        let mut gs = GameState::new(10, 20, 0);
+        // the piece falls to the floor and locks instead of the timer running on
+        gs.tick(Duration::from_secs(60 * 60));
+        assert_eq!(gs.counter, 1);
+        assert_eq!(gs.gravity_timer, Duration::ZERO);
+    }
+}
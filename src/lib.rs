@@ -0,0 +1,11 @@
+// Tetris rules, usable without Bevy by building with `--no-default-features`
+
+pub mod bag;
+pub mod board;
+pub mod gameconfig;
+pub mod gamescore;
+pub mod gamestate;
+pub mod randomizer;
+pub mod scoring;
+pub mod tetlib;
+pub mod tetrominoe;
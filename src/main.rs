@@ -1,25 +1,21 @@
 // Tetris
 
 use bevy::{
-    app::AppExit,
     diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
     prelude::*,
     time::Stopwatch,
     window::PresentMode,
 };
 
-use gamestate::GameState;
-use tetlib::*;
-use tetrominoe::{State, Tetrominoe};
+use std::time::Duration;
 
-mod bag;
-mod gamescore;
-mod gamestate;
-mod tetlib;
-mod tetrominoe;
-
-#[derive(Resource)]
-struct GameTimer(Timer);
+use bevy_tetris_web::{
+    gameconfig::{GameConfig, GameMode, LevelUp},
+    gamestate::GameState,
+    randomizer::RandomizerKind,
+    tetlib::*,
+    tetrominoe::{Piece, TColor},
+};
 
 #[derive(Component)]
 struct Block;
@@ -33,14 +29,72 @@ struct Level;
 #[derive(Component)]
 struct WatchText;
 
+#[derive(Component)]
+struct Announcement;
+
+#[derive(Component)]
+struct Stats;
+
+#[derive(Component)]
+struct Banner;
+
+#[derive(Resource)]
+struct AnnouncementTimer(Timer);
+
+#[derive(Component)]
+struct Overlay;
+
+#[derive(Component)]
+struct OverlayText;
+
+#[derive(States, Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+enum AppState {
+    #[default]
+    Menu,
+    Countdown,
+    Playing,
+    Paused,
+    GameOver,
+}
+
+#[derive(Resource)]
+struct Countdown(Timer);
+
 #[derive(Resource)]
 struct Watch {
     time: Stopwatch,
 }
 
+#[derive(Resource)]
+struct Handling {
+    das: Duration,
+    arr: Duration,
+    sdf: u32,
+}
+
+impl Default for Handling {
+    fn default() -> Self {
+        Handling {
+            das: Duration::from_millis(167),
+            arr: Duration::from_millis(33),
+            sdf: 20,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct AutoShift {
+    key: Option<KeyCode>,
+    charge: Duration,
+    repeat: Duration,
+    soft_drop: Duration,
+}
+
 const WIDTH: usize = 10;
 const HEIGHT: usize = 20;
 
+const WINDOW_SIZE: f32 = 600.0;
+
 const LEFT: i32 = -110;
 const TOP: i32 = 200;
 
@@ -81,6 +135,86 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>, audio: Res<Audi
         }),
     );
 
+    // Line clears and T-spins, under the held piece
+    commands.spawn((
+        Announcement,
+        TextBundle::from_sections([TextSection::from_style(TextStyle {
+            font: asset_server.load("font/Nineteen-Ninety-Seven.otf"),
+            font_size: FONT_SIZE - 5.,
+            color: Color::WHITE,
+        })])
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(TOP as f32 + TEXT_TOP_PADDING + 165.),
+                left: Val::Px(LEFT as f32 + LEFT_TEXT_PADDING - 35.),
+                ..default()
+            },
+            ..default()
+        }),
+    ));
+
+    // Lines, combo and back-to-back counters
+    commands.spawn((
+        Stats,
+        TextBundle::from_sections([
+            TextSection::new(
+                "LINES: ",
+                TextStyle {
+                    font: asset_server.load("font/Nineteen-Ninety-Seven.otf"),
+                    font_size: FONT_SIZE - 5.,
+                    color: Color::WHITE,
+                },
+            ),
+            TextSection::from_style(TextStyle {
+                font: asset_server.load("font/Nineteen-Ninety-Seven.otf"),
+                font_size: FONT_SIZE - 5.,
+                color: Color::WHITE,
+            }),
+            TextSection::new(
+                "\nCOMBO: ",
+                TextStyle {
+                    font: asset_server.load("font/Nineteen-Ninety-Seven.otf"),
+                    font_size: FONT_SIZE - 5.,
+                    color: Color::WHITE,
+                },
+            ),
+            TextSection::from_style(TextStyle {
+                font: asset_server.load("font/Nineteen-Ninety-Seven.otf"),
+                font_size: FONT_SIZE - 5.,
+                color: Color::WHITE,
+            }),
+            TextSection::new(
+                "\nB2B: ",
+                TextStyle {
+                    font: asset_server.load("font/Nineteen-Ninety-Seven.otf"),
+                    font_size: FONT_SIZE - 5.,
+                    color: Color::WHITE,
+                },
+            ),
+            TextSection::from_style(TextStyle {
+                font: asset_server.load("font/Nineteen-Ninety-Seven.otf"),
+                font_size: FONT_SIZE - 5.,
+                color: Color::WHITE,
+            }),
+            // grade, left empty for rule sets without one
+            TextSection::from_style(TextStyle {
+                font: asset_server.load("font/Nineteen-Ninety-Seven.otf"),
+                font_size: FONT_SIZE - 5.,
+                color: Color::WHITE,
+            }),
+        ])
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(TOP as f32 + TEXT_TOP_PADDING + 265.),
+                left: Val::Px(LEFT as f32 + LEFT_TEXT_PADDING - 35.),
+                ..default()
+            },
+            ..default()
+        }),
+    ));
+
     // Scoreboard
     // Score
     commands.spawn((
@@ -167,6 +301,93 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>, audio: Res<Audi
         }),
     ));
 
+    // Perfect clear banner over the board
+    commands
+        .spawn((
+            Banner,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        top: Val::Px(WINDOW_SIZE / 2. - TOP as f32 - BLOCK_SIZE / 2.),
+                        left: Val::Px(WINDOW_SIZE / 2. + LEFT as f32 - BLOCK_SIZE / 2.),
+                        ..default()
+                    },
+                    size: Size::new(
+                        Val::Px(WIDTH as f32 * BLOCK_SIZE),
+                        Val::Px(HEIGHT as f32 * BLOCK_SIZE),
+                    ),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(
+                TextBundle::from_sections([TextSection::new(
+                    "PERFECT\nCLEAR",
+                    TextStyle {
+                        font: asset_server.load("font/Nineteen-Ninety-Seven.otf"),
+                        font_size: FONT_SIZE + 10.,
+                        color: Color::YELLOW,
+                    },
+                )])
+                .with_text_alignment(TextAlignment::Center),
+            );
+        });
+
+    // Menu, countdown, pause and game over messages, covering the board
+    commands
+        .spawn((
+            Overlay,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        top: Val::Px(WINDOW_SIZE / 2. - TOP as f32 - BLOCK_SIZE / 2.),
+                        left: Val::Px(WINDOW_SIZE / 2. + LEFT as f32 - BLOCK_SIZE / 2.),
+                        ..default()
+                    },
+                    size: Size::new(
+                        Val::Px(WIDTH as f32 * BLOCK_SIZE),
+                        Val::Px(HEIGHT as f32 * BLOCK_SIZE),
+                    ),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.8).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                OverlayText,
+                TextBundle::from_sections([
+                    TextSection::from_style(TextStyle {
+                        font: asset_server.load("font/Nineteen-Ninety-Seven.otf"),
+                        font_size: FONT_SIZE,
+                        color: Color::WHITE,
+                    }),
+                    TextSection::from_style(TextStyle {
+                        font: asset_server.load("font/Nineteen-Ninety-Seven.otf"),
+                        font_size: FONT_SIZE - 5.,
+                        color: Color::WHITE,
+                    }),
+                    TextSection::from_style(TextStyle {
+                        font: asset_server.load("font/Nineteen-Ninety-Seven.otf"),
+                        font_size: FONT_SIZE - 5.,
+                        color: Color::WHITE,
+                    }),
+                ])
+                .with_text_alignment(TextAlignment::Center),
+            ));
+        });
+
     // top
     for i in 0..WIDTH {
         commands.spawn(SpriteBundle {
@@ -239,39 +460,303 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>, audio: Res<Audi
     }
 }
 
-fn gravity_system(
+fn gravity_system(mut gs: ResMut<GameState>, time: Res<Time>) {
+    gs.tick(time.delta());
+}
+
+fn menu_system(
+    mut gs: ResMut<GameState>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    // left and right pick the game mode, up and down override its randomizer,
+    // number keys pick the starting level and L switches how levels go up
+    let digits = [
+        KeyCode::Key0,
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+    let mut config = gs.config.clone();
+    let mut changed = true;
+    if keyboard_input.just_pressed(KeyCode::Left) || keyboard_input.just_pressed(KeyCode::Right) {
+        let step = if keyboard_input.just_pressed(KeyCode::Left) {
+            -1
+        } else {
+            1
+        };
+        config = GameConfig {
+            start_level: config.start_level,
+            ..cycle(&GameMode::ALL, config.mode, step).config()
+        };
+    } else if keyboard_input.just_pressed(KeyCode::Up) {
+        config.randomizer = cycle(&RandomizerKind::ALL, config.randomizer, -1);
+    } else if keyboard_input.just_pressed(KeyCode::Down) {
+        config.randomizer = cycle(&RandomizerKind::ALL, config.randomizer, 1);
+    } else if keyboard_input.just_pressed(KeyCode::L) {
+        config.level_up = match config.level_up {
+            LevelUp::Lines(_) => LevelUp::Score(1000),
+            LevelUp::Score(_) => LevelUp::Lines(10),
+        };
+    } else if let Some(level) = digits.iter().position(|&key| keyboard_input.just_pressed(key)) {
+        config.start_level = level as u32;
+    } else {
+        changed = false;
+    }
+
+    if changed {
+        *gs = GameState::with_config(WIDTH, HEIGHT, gs.seed(), config);
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        next_state.set(AppState::Countdown);
+    }
+}
+
+fn cycle<T: Copy + PartialEq>(all: &[T], current: T, step: isize) -> T {
+    let index = all.iter().position(|&item| item == current).unwrap_or(0) as isize;
+    all[(index + step).rem_euclid(all.len() as isize) as usize]
+}
+
+fn start_countdown(mut countdown: ResMut<Countdown>) {
+    countdown.0.reset();
+}
+
+fn countdown_system(
+    time: Res<Time>,
+    mut countdown: ResMut<Countdown>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if countdown.0.tick(time.delta()).just_finished() {
+        next_state.set(AppState::Playing);
+    }
+}
+
+// pausing is immediate, resuming goes through the countdown again
+fn pause_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::P) {
+        match state.0 {
+            AppState::Playing => next_state.set(AppState::Paused),
+            AppState::Paused => next_state.set(AppState::Countdown),
+            _ => (),
+        }
+    }
+}
+
+fn top_out_system(gs: Res<GameState>, mut next_state: ResMut<NextState<AppState>>) {
+    if gs.is_game_over() {
+        next_state.set(AppState::GameOver);
+    }
+}
+
+fn game_over_system(
+    mut gs: ResMut<GameState>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut stopwatch: ResMut<Watch>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    // retry with a fresh seed, or replay the same piece sequence
+    let seed = if keyboard_input.just_pressed(KeyCode::R)
+        || keyboard_input.just_pressed(KeyCode::Return)
+    {
+        Some(new_seed())
+    } else if keyboard_input.just_pressed(KeyCode::S) {
+        Some(gs.seed())
+    } else {
+        None
+    };
+
+    if let Some(seed) = seed {
+        *gs = GameState::with_config(WIDTH, HEIGHT, seed, gs.config.clone());
+        stopwatch.time.reset();
+        next_state.set(AppState::Countdown);
+    } else if keyboard_input.just_pressed(KeyCode::Q) {
+        // back to the menu rather than exiting, which only freezes the canvas on the web
+        *gs = GameState::with_config(WIDTH, HEIGHT, new_seed(), gs.config.clone());
+        stopwatch.time.reset();
+        next_state.set(AppState::Menu);
+    }
+}
+
+fn overlay_system(
+    state: Res<State<AppState>>,
+    gs: Res<GameState>,
+    stopwatch: Res<Watch>,
+    countdown: Res<Countdown>,
+    mut screen: Query<&mut Visibility, With<Overlay>>,
+    mut text: Query<&mut Text, With<OverlayText>>,
+) {
+    let mut visibility = screen.single_mut();
+    let (title, body, hint) = match state.0 {
+        AppState::Menu => (
+            "TETRIS",
+            format!(
+                "MODE: {}\nPIECES: {}\nSTART LEVEL: {}\nLEVELS: {}",
+                gs.config.mode.name(),
+                gs.config.randomizer.name(),
+                gs.config.start_level,
+                match gs.config.level_up {
+                    LevelUp::Lines(_) => "LINES",
+                    LevelUp::Score(_) => "SCORE",
+                }
+            ),
+            "ARROWS: CHANGE\n0-9: START LEVEL\nL: LEVELS\nENTER: START",
+        ),
+        AppState::Countdown => {
+            let remaining = countdown.0.duration() - countdown.0.elapsed();
+            ("", (remaining.as_secs_f32().ceil() as u32).to_string(), "")
+        }
+        AppState::Playing => {
+            *visibility = Visibility::Hidden;
+            return;
+        }
+        AppState::Paused => ("PAUSED", format!("SEED: {}", gs.seed()), "P: RESUME"),
+        AppState::GameOver => (
+            "GAME OVER",
+            format!(
+                "{}\n\nSCORE: {}\nLEVEL: {}\nTIME: {}{}\nSEED: {}",
+                gs.top_out.map_or("", |top_out| top_out.name()),
+                gs.gamescore.score,
+                gs.gamescore.level,
+                format_time(stopwatch.time.elapsed()),
+                match gs.gamescore.grade() {
+                    Some(grade) => format!("\nGRADE: {}", grade),
+                    None => String::new(),
+                },
+                gs.seed()
+            ),
+            "R: RETRY\nS: SAME SEED\nQ: MENU",
+        ),
+    };
+
+    *visibility = Visibility::Visible;
+    let mut text = text.single_mut();
+    text.sections[0].value = format!("{}\n\n", title);
+    text.sections[1].value = body;
+    text.sections[2].value = format!("\n\n{}", hint);
+}
+
+// the board is hidden on the title menu and while paused
+fn board_visible(state: Res<State<AppState>>) -> bool {
+    !matches!(state.0, AppState::Menu | AppState::Paused)
+}
+
+fn handle_input_system(
     mut gs: ResMut<GameState>,
-    mut timer: ResMut<GameTimer>,
+    keyboard_input: Res<Input<KeyCode>>,
     time: Res<Time>,
-    mut app_exit_events: ResMut<Events<AppExit>>,
+    handling: Res<Handling>,
+    mut shift: ResMut<AutoShift>,
 ) {
-    if timer.0.tick(time.delta()).just_finished() && gravity(&mut gs) {
-        app_exit_events.send(AppExit);
+    // left and right move once when pressed, then repeat every ARR once DAS has charged
+    for key in [KeyCode::Left, KeyCode::Right] {
+        if keyboard_input.just_pressed(key) {
+            gs.apply(shift_direction(key));
+            shift.key = Some(key);
+            shift.charge = Duration::ZERO;
+            shift.repeat = Duration::ZERO;
+        }
+    }
+
+    if let Some(key) = shift.key {
+        if keyboard_input.pressed(key) {
+            let charged = shift.charge >= handling.das;
+            shift.charge += time.delta();
+            if shift.charge >= handling.das {
+                if charged {
+                    shift.repeat += time.delta();
+                } else {
+                    shift.repeat = handling.arr;
+                }
+                repeat_input(&mut gs, shift_direction(key), &mut shift.repeat, handling.arr);
+            }
+        } else {
+            // fall back to the other direction if it is still held
+            shift.key = [KeyCode::Left, KeyCode::Right]
+                .into_iter()
+                .find(|key| keyboard_input.pressed(*key));
+            shift.charge = Duration::ZERO;
+            shift.repeat = Duration::ZERO;
+        }
+    }
+
+    // soft drop repeats at the gravity speed multiplied by the soft drop factor
+    if keyboard_input.just_pressed(KeyCode::Down) {
+        gs.apply(Action::SoftDrop);
+        shift.soft_drop = Duration::ZERO;
+    } else if keyboard_input.pressed(KeyCode::Down) {
+        shift.soft_drop += time.delta();
+        let interval = gs.config.gravity.interval(gs.gamescore.level) / handling.sdf;
+        repeat_input(&mut gs, Action::SoftDrop, &mut shift.soft_drop, interval);
+    }
+
+    let bindings = [
+        (KeyCode::Space, Action::HardDrop),
+        (KeyCode::Up, Action::RotateCW),
+        (KeyCode::Z, Action::RotateCCW),
+        (KeyCode::A, Action::Rotate180),
+        (KeyCode::C, Action::Hold),
+    ];
+    if let Some(&(_, action)) = bindings
+        .iter()
+        .find(|(key, _)| keyboard_input.just_pressed(*key))
+    {
+        gs.apply(action);
     }
 }
 
-fn handle_input_system(mut gs: ResMut<GameState>, keyboard_input: Res<Input<KeyCode>>) {
-    if keyboard_input.just_pressed(KeyCode::Left) {
-        handle_input(&mut gs, 'l');
-    } else if keyboard_input.just_pressed(KeyCode::Right) {
-        handle_input(&mut gs, 'r');
-    } else if keyboard_input.just_pressed(KeyCode::Space) {
-        handle_input(&mut gs, 's');
-    } else if keyboard_input.just_pressed(KeyCode::Down) {
-        handle_input(&mut gs, 'd');
-    } else if keyboard_input.just_pressed(KeyCode::Up) {
-        handle_input(&mut gs, 'u');
-    } else if keyboard_input.just_pressed(KeyCode::C) {
-        hold(&mut gs);
+fn shift_direction(key: KeyCode) -> Action {
+    match key {
+        KeyCode::Left => Action::MoveLeft,
+        _ => Action::MoveRight,
     }
 }
 
-fn ghost_piece_system(mut gs: ResMut<GameState>) {
-    ghost_piece(&mut gs);
+// apply the action once per interval that has elapsed, stopping early once the piece is blocked
+fn repeat_input(gs: &mut GameState, action: Action, elapsed: &mut Duration, interval: Duration) {
+    while *elapsed >= interval {
+        *elapsed -= interval;
+        if !gs.apply(action).success {
+            *elapsed = Duration::ZERO;
+            break;
+        }
+    }
 }
 
-fn full_line_system(mut gs: ResMut<GameState>) {
-    full_line(&mut gs);
+// show the last line clear or T-spin, and the banner for a perfect clear, for a couple of seconds
+fn announce_system(
+    mut gs: ResMut<GameState>,
+    time: Res<Time>,
+    mut timer: ResMut<AnnouncementTimer>,
+    mut query: Query<&mut Text, With<Announcement>>,
+    mut banner: Query<&mut Visibility, With<Banner>>,
+) {
+    let mut text = query.single_mut();
+    let mut banner = banner.single_mut();
+    if let Some(clear) = gs.gamescore.last_clear.take() {
+        text.sections[0].value = clear.name();
+        *banner = if clear.perfect {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+        timer.0.reset();
+    }
+
+    if timer.0.tick(time.delta()).just_finished() {
+        text.sections[0].value.clear();
+        *banner = Visibility::Hidden;
+    }
 }
 
 fn update_score_system(gs: Res<GameState>, mut query: Query<&mut Text, With<Score>>) {
@@ -286,133 +771,173 @@ fn update_level_system(gs: Res<GameState>, mut query: Query<&mut Text, With<Leve
     text.sections[1].style.font_size = FONT_SIZE - (text.sections[1].value.len() / 4) as f32 * 5.;
 }
 
+// combo and back-to-back count the clear that started them, so the first bonus shows as 1
+fn update_stats_system(gs: Res<GameState>, mut query: Query<&mut Text, With<Stats>>) {
+    let mut text = query.single_mut();
+    text.sections[1].value = gs.gamescore.lines.to_string();
+    text.sections[3].value = gs.gamescore.combo.saturating_sub(1).to_string();
+    text.sections[5].value = gs.gamescore.back_to_back.saturating_sub(1).to_string();
+    text.sections[6].value = match gs.gamescore.grade() {
+        Some(grade) => format!("\nGRADE: {}", grade),
+        None => String::new(),
+    };
+}
+
 fn update_stopwatch_system(
     time: Res<Time>,
     mut stopwatch: ResMut<Watch>,
     mut query: Query<&mut Text, With<WatchText>>,
 ) {
     let mut text = query.single_mut();
-    text.sections[1].value = format!(
-        "{}:{:02}",
-        stopwatch.time.elapsed().as_secs() / 60,
-        stopwatch.time.elapsed().as_secs() % 60
-    );
+    text.sections[1].value = format_time(stopwatch.time.elapsed());
     text.sections[1].style.font_size = FONT_SIZE - (text.sections[1].value.len() / 7) as f32 * 5.;
     stopwatch.time.tick(time.delta());
 }
 
+// kept to 32 bits so it stays short enough to read off the screen
+fn new_seed() -> u64 {
+    rand::random::<u32>().into()
+}
+
+fn format_time(elapsed: Duration) -> String {
+    format!("{}:{:02}", elapsed.as_secs() / 60, elapsed.as_secs() % 60)
+}
+
 fn render_next(gs: Res<GameState>, mut commands: Commands, asset_server: Res<AssetServer>) {
-    for row in 0..gs.next_piece.shape.len() {
-        for col in 0..gs.next_piece.shape[row].len() {
-            if gs.next_piece.shape[row][col] == 'a' {
-                commands.spawn((
-                    Block,
-                    SpriteBundle {
-                        texture: asset_server.load(gs.next_piece.as_color()),
-                        sprite: Sprite {
-                            custom_size: Some(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                            ..default()
-                        },
-                        transform: Transform::from_translation(Vec3::new(
-                            (LEFT + col as i32 * BLOCK_SIZE as i32) as f32 + 270.,
-                            (TOP - row as i32 * BLOCK_SIZE as i32) as f32 - 125.,
-                            0.,
-                        )),
+    // queue runs down the right of the board, three rows per piece
+    for (index, piece) in gs.next_pieces().enumerate() {
+        let offset = index as f32 * 3. * BLOCK_SIZE;
+        for (row, col) in Piece::shape(piece.ptype, 0) {
+            commands.spawn((
+                Block,
+                SpriteBundle {
+                    texture: asset_server.load(piece.color.texture()),
+                    sprite: Sprite {
+                        custom_size: Some(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
                         ..default()
                     },
-                ));
-            }
+                    transform: Transform::from_translation(Vec3::new(
+                        (LEFT + col * BLOCK_SIZE as i32) as f32 + 270.,
+                        (TOP - row * BLOCK_SIZE as i32) as f32 - 125. - offset,
+                        0.,
+                    )),
+                    ..default()
+                },
+            ));
         }
     }
 }
 
 fn render_hold(gs: Res<GameState>, mut commands: Commands, asset_server: Res<AssetServer>) {
-    match &gs.hold_piece {
-        Some(piece) => {
-            let mut blank = Tetrominoe::new(None, None);
-            let upright = blank.set(piece.ptype);
-            for row in 0..upright.shape.len() {
-                for col in 0..upright.shape[row].len() {
-                    if upright.shape[row][col] == 'a' {
-                        commands.spawn((
-                            Block,
-                            SpriteBundle {
-                                texture: asset_server.load(upright.as_color()),
-                                sprite: Sprite {
-                                    custom_size: Some(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                                    ..default()
-                                },
-                                transform: Transform::from_translation(Vec3::new(
-                                    (LEFT + col as i32 * BLOCK_SIZE as i32) as f32 - 119.,
-                                    (TOP - row as i32 * BLOCK_SIZE as i32) as f32 - 20.,
-                                    0.,
-                                )),
-                                ..default()
-                            },
-                        ));
-                    }
-                }
-            }
+    if let Some(piece) = &gs.hold_piece {
+        // greyed out until the current piece locks and hold is available again
+        let texture = if gs.hold_used {
+            TColor::Gray.texture()
+        } else {
+            piece.color.texture()
+        };
+
+        // upright, in its spawn rotation
+        for (row, col) in Piece::shape(piece.ptype, 0) {
+            commands.spawn((
+                Block,
+                SpriteBundle {
+                    texture: asset_server.load(texture),
+                    sprite: Sprite {
+                        custom_size: Some(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(Vec3::new(
+                        (LEFT + col * BLOCK_SIZE as i32) as f32 - 119.,
+                        (TOP - row * BLOCK_SIZE as i32) as f32 - 20.,
+                        0.,
+                    )),
+                    ..default()
+                },
+            ));
         }
-
-        None => (),
     }
 }
 
 fn render_system(gs: Res<GameState>, mut commands: Commands, asset_server: Res<AssetServer>) {
-    for row in gs.display.iter().enumerate() {
-        for col in row.1.iter().enumerate() {
-            match col.1.game_state {
-                State::Landed | State::Active => {
-                    commands.spawn((
-                        Block,
-                        SpriteBundle {
-                            texture: asset_server.load(col.1.as_color()),
-                            sprite: Sprite {
-                                // color: col.1.as_color(),
-                                custom_size: Some(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                                ..default()
-                            },
-                            transform: Transform::from_translation(Vec3::new(
-                                (LEFT + col.0 as i32 * BLOCK_SIZE as i32) as f32,
-                                (TOP - row.0 as i32 * BLOCK_SIZE as i32) as f32,
-                                0.,
-                            )),
-                            ..default()
-                        },
-                    ));
-                    // print!("A")
-                }
-                State::Ghost => {
-                    commands.spawn((
-                        Block,
-                        SpriteBundle {
-                            sprite: Sprite {
-                                color: Color::Rgba {
-                                    red: 1.,
-                                    green: 1.,
-                                    blue: 1.,
-                                    alpha: 0.1,
-                                },
-                                custom_size: Some(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                                ..default()
-                            },
-                            transform: Transform::from_translation(Vec3::new(
-                                (LEFT + col.0 as i32 * BLOCK_SIZE as i32) as f32,
-                                (TOP - row.0 as i32 * BLOCK_SIZE as i32) as f32,
-                                0.,
-                            )),
-                            ..default()
-                        },
-                    ));
-                    // print!("G")
-                }
-                _ => {
-                    // print!(".")
-                }
+    let hidden_rows = gs.config.hidden_rows;
+    for row in hidden_rows..gs.board.height() {
+        for col in 0..gs.board.width() {
+            if !gs.board.filled(row as i32, col as i32) {
+                continue;
             }
+
+            commands.spawn((
+                Block,
+                SpriteBundle {
+                    texture: asset_server.load(gs.board.color(row, col).texture()),
+                    sprite: Sprite {
+                        custom_size: Some(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(Vec3::new(
+                        (LEFT + col as i32 * BLOCK_SIZE as i32) as f32,
+                        (TOP - (row - hidden_rows) as i32 * BLOCK_SIZE as i32) as f32,
+                        0.,
+                    )),
+                    ..default()
+                },
+            ));
         }
-        // println!()
+    }
+
+    // the falling piece and its ghost are not part of the board, draw the parts of them
+    // that are visible
+    let hidden_rows = hidden_rows as i32;
+    let active = gs.active_piece.cells();
+    for (row, col) in ghost_piece(&gs).cells() {
+        if row < hidden_rows || active.contains(&(row, col)) {
+            continue;
+        }
+        commands.spawn((
+            Block,
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::Rgba {
+                        red: 1.,
+                        green: 1.,
+                        blue: 1.,
+                        alpha: 0.1,
+                    },
+                    custom_size: Some(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                    ..default()
+                },
+                transform: Transform::from_translation(Vec3::new(
+                    (LEFT + col * BLOCK_SIZE as i32) as f32,
+                    (TOP - (row - hidden_rows) * BLOCK_SIZE as i32) as f32,
+                    0.,
+                )),
+                ..default()
+            },
+        ));
+    }
+
+    let texture = gs.active_piece.color().texture();
+    for (row, col) in active {
+        if row < hidden_rows {
+            continue;
+        }
+        commands.spawn((
+            Block,
+            SpriteBundle {
+                texture: asset_server.load(texture),
+                sprite: Sprite {
+                    custom_size: Some(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                    ..default()
+                },
+                transform: Transform::from_translation(Vec3::new(
+                    (LEFT + col * BLOCK_SIZE as i32) as f32,
+                    (TOP - (row - hidden_rows) * BLOCK_SIZE as i32) as f32,
+                    0.,
+                )),
+                ..default()
+            },
+        ));
     }
 }
 
@@ -423,12 +948,14 @@ fn move_sprites(mut commands: Commands, query: Query<Entity, With<Block>>) {
 }
 
 fn main() {
+    let gs = GameState::new(WIDTH, HEIGHT, new_seed());
+
     App::new()
         .insert_resource(ClearColor(Color::rgb(0.1, 0.1, 0.1)))
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
                 title: "Tetris".into(),
-                resolution: (600., 600.).into(),
+                resolution: (WINDOW_SIZE, WINDOW_SIZE).into(),
                 present_mode: PresentMode::AutoVsync,
                 // fit_canvas_to_parent: true,
                 prevent_default_event_handling: false,
@@ -438,23 +965,39 @@ fn main() {
         }))
         .add_plugin(LogDiagnosticsPlugin::default())
         .add_plugin(FrameTimeDiagnosticsPlugin)
-        .insert_resource(GameState::new(10, 20))
+        .insert_resource(gs)
         .insert_resource(Watch {
             time: Stopwatch::new(),
         })
-        .insert_resource(GameTimer(Timer::from_seconds(0.4, TimerMode::Repeating)))
+        .init_resource::<Handling>()
+        .init_resource::<AutoShift>()
+        .insert_resource(AnnouncementTimer(Timer::from_seconds(2., TimerMode::Once)))
+        .insert_resource(Countdown(Timer::from_seconds(3., TimerMode::Once)))
+        .add_state::<AppState>()
         .add_startup_system(setup)
+        .add_system(menu_system.in_set(OnUpdate(AppState::Menu)))
+        .add_system(start_countdown.in_schedule(OnEnter(AppState::Countdown)))
+        .add_system(countdown_system.in_set(OnUpdate(AppState::Countdown)))
+        .add_systems(
+            (
+                gravity_system,
+                handle_input_system,
+                announce_system,
+                update_stopwatch_system,
+                top_out_system,
+            )
+                .in_set(OnUpdate(AppState::Playing)),
+        )
+        .add_system(game_over_system.in_set(OnUpdate(AppState::GameOver)))
         .add_systems((
-            gravity_system,
-            handle_input_system,
-            ghost_piece_system,
-            full_line_system,
+            pause_system,
+            overlay_system,
             update_score_system,
             update_level_system,
-            update_stopwatch_system,
-            render_hold,
-            render_next,
-            render_system.after(handle_input_system),
+            update_stats_system,
+            render_hold.run_if(board_visible),
+            render_next.run_if(board_visible),
+            render_system.after(handle_input_system).run_if(board_visible),
             move_sprites,
         ))
         .add_system(bevy::window::close_on_esc)
@@ -0,0 +1,237 @@
+use std::collections::VecDeque;
+
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::bag::Bag;
+
+pub const PIECES: [char; 7] = ['I', 'J', 'L', 'O', 'S', 'T', 'Z'];
+
+pub trait Randomizer: Send + Sync {
+    fn draw(&mut self) -> char;
+    fn seed(&self) -> u64;
+    fn box_clone(&self) -> Box<dyn Randomizer>;
+}
+
+impl Clone for Box<dyn Randomizer> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RandomizerKind {
+    SevenBag,
+    FourteenBag,
+    Random,
+    Nes,
+    Tgm,
+}
+
+impl RandomizerKind {
+    pub const ALL: [RandomizerKind; 5] = [
+        RandomizerKind::SevenBag,
+        RandomizerKind::FourteenBag,
+        RandomizerKind::Random,
+        RandomizerKind::Nes,
+        RandomizerKind::Tgm,
+    ];
+
+    pub fn build(&self, seed: u64) -> Box<dyn Randomizer> {
+        match self {
+            RandomizerKind::SevenBag => Box::new(Bag::new(seed)),
+            RandomizerKind::FourteenBag => Box::new(Bag::with_copies(seed, 2)),
+            RandomizerKind::Random => Box::new(PureRandom::new(seed)),
+            RandomizerKind::Nes => Box::new(NesRandomizer::new(seed)),
+            RandomizerKind::Tgm => Box::new(TgmRandomizer::new(seed, 4)),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            RandomizerKind::SevenBag => "7-BAG",
+            RandomizerKind::FourteenBag => "14-BAG",
+            RandomizerKind::Random => "RANDOM",
+            RandomizerKind::Nes => "NES",
+            RandomizerKind::Tgm => "TGM",
+        }
+    }
+}
+
+// every piece equally likely, with no memory at all
+#[derive(Clone, Debug)]
+pub struct PureRandom {
+    rng: ChaCha8Rng,
+    seed: u64,
+}
+
+impl PureRandom {
+    pub fn new(seed: u64) -> Self {
+        PureRandom {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            seed,
+        }
+    }
+}
+
+impl Randomizer for PureRandom {
+    fn draw(&mut self) -> char {
+        *PIECES.choose(&mut self.rng).unwrap()
+    }
+
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn box_clone(&self) -> Box<dyn Randomizer> {
+        Box::new(self.clone())
+    }
+}
+
+// NES: roll an eighth "reroll" slot alongside the seven pieces, and roll once more
+// (without the extra slot) when that or a repeat of the last piece comes up
+#[derive(Clone, Debug)]
+pub struct NesRandomizer {
+    rng: ChaCha8Rng,
+    seed: u64,
+    last: Option<char>,
+}
+
+impl NesRandomizer {
+    pub fn new(seed: u64) -> Self {
+        NesRandomizer {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            seed,
+            last: None,
+        }
+    }
+}
+
+impl Randomizer for NesRandomizer {
+    fn draw(&mut self) -> char {
+        // u32 rather than usize so wasm and native roll the same numbers
+        let index = self.rng.gen_range(0..=PIECES.len() as u32) as usize;
+
+        let piece = match PIECES.get(index) {
+            Some(&piece) if Some(piece) != self.last => piece,
+            _ => *PIECES.choose(&mut self.rng).unwrap(),
+        };
+        self.last = Some(piece);
+        piece
+    }
+
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn box_clone(&self) -> Box<dyn Randomizer> {
+        Box::new(self.clone())
+    }
+}
+
+// TGM: reroll up to `rolls` times while the piece is one of the last four dealt,
+// never starting with S, Z or O
+#[derive(Clone, Debug)]
+pub struct TgmRandomizer {
+    rng: ChaCha8Rng,
+    seed: u64,
+    rolls: u32,
+    history: VecDeque<char>,
+    first: bool,
+}
+
+impl TgmRandomizer {
+    pub fn new(seed: u64, rolls: u32) -> Self {
+        TgmRandomizer {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            seed,
+            rolls,
+            history: VecDeque::from(['Z', 'Z', 'Z', 'Z']),
+            first: true,
+        }
+    }
+}
+
+impl Randomizer for TgmRandomizer {
+    fn draw(&mut self) -> char {
+        let piece = if self.first {
+            self.first = false;
+            *['I', 'J', 'L', 'T'].choose(&mut self.rng).unwrap()
+        } else {
+            let mut piece = *PIECES.choose(&mut self.rng).unwrap();
+            for _ in 1..self.rolls {
+                if !self.history.contains(&piece) {
+                    break;
+                }
+                piece = *PIECES.choose(&mut self.rng).unwrap();
+            }
+            piece
+        };
+
+        self.history.pop_front();
+        self.history.push_back(piece);
+        piece
+    }
+
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn box_clone(&self) -> Box<dyn Randomizer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gameconfig::GameConfig, gamestate::GameState};
+
+    fn draw(kind: RandomizerKind, seed: u64, count: usize) -> String {
+        let mut randomizer = kind.build(seed);
+        (0..count).map(|_| randomizer.draw()).collect()
+    }
+
+    // pinned so a change in the generator, or a platform dependent draw, shows up here
+    #[test]
+    fn seeded_sequences_are_stable() {
+        let expected = [
+            (RandomizerKind::SevenBag, "JSLZTOIJSTLZOI"),
+            (RandomizerKind::FourteenBag, "OTLJZJLOSTISZI"),
+            (RandomizerKind::Random, "JSJZTLSTLSJLLJ"),
+            (RandomizerKind::Nes, "TJZOTLTJLJZTZS"),
+            (RandomizerKind::Tgm, "TLSJTJOLZSTLJI"),
+        ];
+        for (kind, sequence) in expected {
+            assert_eq!(draw(kind, 42, 14), sequence, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_same_game() {
+        for kind in RandomizerKind::ALL {
+            let config = GameConfig {
+                randomizer: kind,
+                ..GameConfig::default()
+            };
+            let mut first = GameState::with_config(10, 20, 7, config.clone());
+            let mut second = GameState::with_config(10, 20, 7, config);
+            assert_eq!(first.seed(), 7);
+            assert_eq!(first.active_piece, second.active_piece);
+
+            let pieces =
+                |gs: &mut GameState| -> String { (0..100).map(|_| gs.pop_next_piece()).collect() };
+            assert_eq!(pieces(&mut first), pieces(&mut second), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn bags_deal_every_piece_once_per_bag() {
+        let sequence: Vec<char> = draw(RandomizerKind::SevenBag, 3, 70).chars().collect();
+        for bag in sequence.chunks(7) {
+            let mut bag = bag.to_vec();
+            bag.sort();
+            assert_eq!(bag, PIECES);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::gamescore::{Clear, Spin};
+
+// consulted by GameScore on every drop and every lock, with the clear already
+// carrying its combo and back-to-back state
+pub trait ScoringRules: Send + Sync {
+    fn clear_points(&mut self, clear: &Clear, level: u32) -> u32;
+    fn drop_points(&mut self, rows: u32, hard: bool) -> u32;
+    fn box_clone(&self) -> Box<dyn ScoringRules>;
+
+    // grade earned with a score, for rule sets that have them
+    fn grade(&self, _score: u32) -> Option<&'static str> {
+        None
+    }
+}
+
+impl Clone for Box<dyn ScoringRules> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScoringKind {
+    Guideline,
+    Nes,
+    Tgm,
+}
+
+impl ScoringKind {
+    pub fn build(&self) -> Box<dyn ScoringRules> {
+        match self {
+            ScoringKind::Guideline => Box::new(GuidelineScoring),
+            ScoringKind::Nes => Box::new(NesScoring),
+            ScoringKind::Tgm => Box::new(TgmScoring::default()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GuidelineScoring;
+
+impl ScoringRules for GuidelineScoring {
+    fn clear_points(&mut self, clear: &Clear, level: u32) -> u32 {
+        let mut points = match (clear.spin, clear.lines) {
+            (Spin::None, 0) => 0,
+            (Spin::None, 1) => 100,
+            (Spin::None, 2) => 300,
+            (Spin::None, 3) => 500,
+            (Spin::None, _) => 800,
+            (Spin::Mini, 0) => 100,
+            (Spin::Mini, 1) => 200,
+            (Spin::Mini, _) => 400,
+            (Spin::Full, 0) => 400,
+            (Spin::Full, 1) => 800,
+            (Spin::Full, 2) => 1200,
+            (Spin::Full, _) => 1600,
+        };
+
+        if clear.back_to_back {
+            points = points * 3 / 2;
+        }
+
+        if clear.perfect {
+            points += match clear.lines {
+                1 => 800,
+                2 => 1200,
+                3 => 1800,
+                _ if clear.back_to_back => 3200,
+                _ => 2000,
+            };
+        }
+
+        if clear.combo > 1 {
+            points += 50 * (clear.combo - 1);
+        }
+
+        points * (level + 1)
+    }
+
+    fn drop_points(&mut self, rows: u32, hard: bool) -> u32 {
+        if hard {
+            2 * rows
+        } else {
+            rows
+        }
+    }
+
+    fn box_clone(&self) -> Box<dyn ScoringRules> {
+        Box::new(*self)
+    }
+}
+
+// lines only, no spins or streaks, and no hard drop to score
+#[derive(Clone, Copy, Debug)]
+pub struct NesScoring;
+
+impl ScoringRules for NesScoring {
+    fn clear_points(&mut self, clear: &Clear, level: u32) -> u32 {
+        let points = match clear.lines {
+            0 => 0,
+            1 => 40,
+            2 => 100,
+            3 => 300,
+            _ => 1200,
+        };
+        points * (level + 1)
+    }
+
+    fn drop_points(&mut self, rows: u32, hard: bool) -> u32 {
+        if hard {
+            0
+        } else {
+            rows
+        }
+    }
+
+    fn box_clone(&self) -> Box<dyn ScoringRules> {
+        Box::new(*self)
+    }
+}
+
+const TGM_GRADES: [(u32, &str); 18] = [
+    (120000, "S9"),
+    (100000, "S8"),
+    (82000, "S7"),
+    (66000, "S6"),
+    (52000, "S5"),
+    (40000, "S4"),
+    (30000, "S3"),
+    (22000, "S2"),
+    (16000, "S1"),
+    (12000, "1"),
+    (8000, "2"),
+    (5500, "3"),
+    (3500, "4"),
+    (2000, "5"),
+    (1400, "6"),
+    (800, "7"),
+    (400, "8"),
+    (0, "9"),
+];
+
+// ((level + lines) / 4 rounded up + soft drop) * lines * combo * bravo, where soft drop
+// counts the rows soft dropped by this piece, combo grows by 2 * lines - 2 for each
+// clearing lock and bravo is 4 for a perfect clear
+#[derive(Clone, Copy, Debug)]
+pub struct TgmScoring {
+    combo: u32,
+    soft: u32,
+}
+
+impl Default for TgmScoring {
+    fn default() -> Self {
+        TgmScoring { combo: 1, soft: 0 }
+    }
+}
+
+impl ScoringRules for TgmScoring {
+    fn clear_points(&mut self, clear: &Clear, level: u32) -> u32 {
+        let soft = std::mem::take(&mut self.soft);
+        if clear.lines == 0 {
+            self.combo = 1;
+            return 0;
+        }
+
+        let lines = clear.lines as u32;
+        self.combo += 2 * lines - 2;
+        let bravo = if clear.perfect { 4 } else { 1 };
+        ((level + lines).div_ceil(4) + soft) * lines * self.combo * bravo
+    }
+
+    fn drop_points(&mut self, rows: u32, hard: bool) -> u32 {
+        if !hard {
+            self.soft += rows;
+        }
+        0
+    }
+
+    fn box_clone(&self) -> Box<dyn ScoringRules> {
+        Box::new(*self)
+    }
+
+    fn grade(&self, score: u32) -> Option<&'static str> {
+        TGM_GRADES
+            .iter()
+            .find(|(points, _)| score >= *points)
+            .map(|(_, grade)| *grade)
+    }
+}
@@ -1,321 +1,651 @@
+use std::time::Duration;
+
 use crate::tetrominoe::Tetrominoe;
+
 use crate::{
-    gamestate::GameState,
-    tetrominoe::{State, TColor},
+    gamescore::{Clear, Spin},
+    gamestate::{GameState, TopOut},
+    tetrominoe::{Piece, Rotation, TColor},
 };
 
-pub const EMP: char = '.';
+pub fn gravity(gs: &mut GameState) -> bool {
+    if gs.is_game_over() || grounded(gs) {
+        return false;
+    }
 
-pub fn init(width: usize, height: usize) -> Vec<Vec<Tetrominoe>> {
-    let mut display: Vec<Vec<Tetrominoe>> = Vec::new();
+    gs.active_piece.row += 1;
+    gs.last_rotation = None;
 
-    // generation
-    for _ in 0..height {
-        display.push(vec![Tetrominoe::default(); width]);
+    // reaching a new lowest row gives back the lock delay and all its resets
+    if gs.active_piece.row > gs.lowest_row {
+        gs.lowest_row = gs.active_piece.row;
+        gs.lock_timer = Duration::ZERO;
+        gs.lock_resets = 0;
     }
-    display
+    true
 }
 
-pub fn gravity(gs: &mut GameState) -> bool {
-    let prev_display = gs.display.clone();
-    for row in (0..gs.display.len()).rev() {
-        for col in 0..gs.display[row].len() {
-            if gs.display[row][col].game_state == State::Active {
-                if row == gs.display.len() - 1
-                    || gs.display[row + 1][col].game_state == State::Landed
-                {
-                    gs.display = prev_display;
-                    landed(gs);
-                    let game_over = new_piece(gs, None);
-                    return game_over;
-                }
+pub fn grounded(gs: &GameState) -> bool {
+    let piece = gs.active_piece;
+    !fits(gs, piece.ptype, piece.row + 1, piece.col, piece.rotation)
+}
 
-                gs.display[row + 1][col] = gs.display[row][col];
-                gs.display[row][col] = Tetrominoe::new(None, None);
-            }
+// whether a piece of this type fits at a position and rotation state, inside the board
+// and clear of locked cells
+pub fn fits(gs: &GameState, piece: char, row: i32, col: i32, rotation: usize) -> bool {
+    gs.board.fits(&Piece::masks(piece, rotation), row, col)
+}
+
+// returns true on game over
+pub fn lock(gs: &mut GameState) -> bool {
+    let spin = t_spin(gs);
+    // lock out if none of the piece made it into the visible playfield
+    let above = hidden(gs);
+    landed(gs);
+    let lines = full_line(gs);
+    let perfect = lines > 0 && board_empty(gs);
+    gs.gamescore.add_clear(Clear::new(lines, spin, perfect));
+
+    gs.counter += 1;
+    gs.hold_used = false;
+    if above {
+        gs.top_out = Some(TopOut::Lock);
+        return true;
+    }
+    new_piece(gs, None)
+}
+
+// whether all of the active piece is in the hidden rows above the visible playfield
+fn hidden(gs: &GameState) -> bool {
+    let hidden_rows = gs.config.hidden_rows as i32;
+    gs.active_piece
+        .cells()
+        .iter()
+        .all(|&(row, _)| row < hidden_rows)
+}
+
+// push the stack up a row and fill the bottom one except for the hole, for whatever
+// sends garbage to this game, returns true on game over. The active piece is pushed
+// up with the stack if it no longer fits
+pub fn add_garbage(gs: &mut GameState, hole: usize) -> bool {
+    if !gs.board.push_garbage(hole, TColor::Gray) {
+        gs.top_out = Some(TopOut::Garbage);
+        return true;
+    }
+
+    let piece = gs.active_piece;
+    if !fits(gs, piece.ptype, piece.row, piece.col, piece.rotation) {
+        if !fits(gs, piece.ptype, piece.row - 1, piece.col, piece.rotation) {
+            gs.top_out = Some(TopOut::Garbage);
+            return true;
         }
+        gs.active_piece.row -= 1;
     }
-    gs.active_piece.row += 1;
     false
 }
 
-pub fn handle_input(gs: &mut GameState, key: char) {
-    let prev_display = gs.display.clone();
-    match key {
-        'l' => {
-            for row in (0..gs.display.len()).rev() {
-                for col in 0..gs.display[row].len() {
-                    if gs.display[row][col].game_state == State::Active {
-                        if col == 0 || gs.display[row][col - 1].game_state == State::Landed {
-                            gs.display = prev_display;
-                            return;
-                        }
-                        gs.display[row][col - 1] = gs.display[row][col];
-                        gs.display[row][col] = Tetrominoe::new(None, None);
-                    }
-                }
-            }
+// 3-corner rule: a T whose last move was a rotation and that has three of the four
+// corners around its center filled is a T-spin. It is a mini unless both corners it
+// points towards are filled, or it got there with the last clockwise/counter-clockwise kick
+fn t_spin(gs: &GameState) -> Spin {
+    let piece = gs.active_piece;
+    let (rotation, kick) = match gs.last_rotation {
+        Some(last_rotation) if piece.ptype == 'T' => last_rotation,
+        _ => return Spin::None,
+    };
+
+    let filled = |row: i32, col: i32| gs.board.filled(row, col);
+
+    // clockwise from the top left, so the two corners in front of rotation state n are n and n + 1
+    let (row, col) = (piece.row + 1, piece.col + 1);
+    let corners = [
+        filled(row - 1, col - 1),
+        filled(row - 1, col + 1),
+        filled(row + 1, col + 1),
+        filled(row + 1, col - 1),
+    ];
+
+    if corners.iter().filter(|&&corner| corner).count() < 3 {
+        return Spin::None;
+    }
 
-            if gs.active_piece.col > 0 {
-                gs.active_piece.col -= 1;
-            }
+    let state = piece.rotation;
+    if (corners[state] && corners[(state + 1) % 4]) || (rotation != Rotation::Half && kick == 4) {
+        Spin::Full
+    } else {
+        Spin::Mini
+    }
+}
+
+pub fn lock_delay(gs: &mut GameState, delta: Duration) -> bool {
+    if gs.is_game_over() {
+        return false;
+    }
+
+    // once the resets are spent, lifting the piece off the stack no longer restarts the
+    // timer, it keeps running from where it was when the piece touches down again
+    if !grounded(gs) {
+        if gs.lock_resets < gs.config.lock_resets {
+            gs.lock_timer = Duration::ZERO;
         }
+        return false;
+    }
 
-        'r' => {
-            for row in (0..gs.display.len()).rev() {
-                for col in (0..gs.display[row].len()).rev() {
-                    if gs.display[row][col].game_state == State::Active {
-                        if col == gs.display[row].len() - 1
-                            || gs.display[row][col + 1].game_state == State::Landed
-                        {
-                            gs.display = prev_display;
-                            return;
-                        }
-                        gs.display[row][col + 1] = gs.display[row][col];
-                        gs.display[row][col] = Tetrominoe::new(None, None);
-                    }
-                }
+    gs.lock_timer += delta;
+    if gs.lock_timer >= gs.config.lock_delay {
+        return lock(gs);
+    }
+    false
+}
+
+// a successful move or rotation while grounded restarts the lock delay, up to the reset limit
+fn reset_lock_delay(gs: &mut GameState) {
+    if gs.lock_timer > Duration::ZERO && gs.lock_resets < gs.config.lock_resets {
+        gs.lock_timer = Duration::ZERO;
+        gs.lock_resets += 1;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateCW,
+    RotateCCW,
+    Rotate180,
+    Hold,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct ActionResult {
+    pub success: bool,
+    // columns moved sideways, negative to the left, or rows dropped
+    pub distance: i32,
+    pub locked: bool,
+    // the action topped out, from locking above the playfield or a blocked spawn
+    pub game_over: bool,
+}
+
+pub fn handle_input(gs: &mut GameState, action: Action) -> ActionResult {
+    if gs.is_game_over() {
+        return ActionResult {
+            game_over: true,
+            ..ActionResult::default()
+        };
+    }
+
+    let moved = |success: bool, distance: i32| ActionResult {
+        success,
+        distance: if success { distance } else { 0 },
+        locked: false,
+        game_over: false,
+    };
+
+    let result = match action {
+        Action::MoveLeft => moved(shift(gs, -1), -1),
+
+        Action::MoveRight => moved(shift(gs, 1), 1),
+
+        Action::SoftDrop => {
+            let success = gravity(gs);
+            if success {
+                gs.gamescore.add_drop(1, false);
             }
-            gs.active_piece.col += 1;
+            moved(success, 1)
         }
 
-        's' => {
-            // bring down piece until new piece is created
-            while gs.display[0][gs.display[0].len() / 2].game_state == State::Empty {
-                gravity(gs);
+        Action::HardDrop => {
+            // bring down piece and lock it without waiting for the lock delay
+            let mut rows = 0;
+            while gravity(gs) {
+                rows += 1;
+            }
+            gs.gamescore.add_drop(rows as u32, true);
+            let game_over = lock(gs);
+            ActionResult {
+                success: true,
+                distance: rows,
+                locked: true,
+                game_over,
             }
         }
 
-        'd' => {
-            gravity(gs);
-        }
+        Action::RotateCW => moved(rotate(gs, Rotation::Clockwise), 0),
 
-        'u' => {
-            // let prev_display = gs.display.clone();
-            let prev_piece = gs.active_piece;
+        Action::RotateCCW => moved(rotate(gs, Rotation::CounterClockwise), 0),
 
-            // rotate piece
-            gs.active_piece.rotate();
-            if gs.active_piece.row + 4 > gs.display.len() {
-                gs.active_piece.row = gs.display.len() - 4;
-            }
+        Action::Rotate180 => moved(rotate(gs, Rotation::Half), 0),
 
-            if gs.active_piece.col + 4 > gs.display[0].len() {
-                gs.active_piece.col = gs.display[0].len() - 4;
-            }
+        Action::Hold => moved(hold(gs), 0),
+    };
 
-            // clear piece and replace with new rotated piece
-            for row in 0..gs.display.len() {
-                for col in 0..gs.display[row].len() {
-                    if gs.display[row][col].game_state == State::Active {
-                        gs.display[row][col] = Tetrominoe::new(None, None);
-                    }
-                }
-            }
+    // a hold can also top out when the next piece has nowhere to spawn
+    ActionResult {
+        game_over: gs.is_game_over(),
+        ..result
+    }
+}
 
-            for row in gs.active_piece.row..gs.active_piece.row + 4 {
-                for col in gs.active_piece.col..gs.active_piece.col + 4 {
-                    if gs.display[row][col].game_state == State::Landed {
-                        gs.display = prev_display;
-                        gs.active_piece = prev_piece;
-                        return;
-                    }
-
-                    if gs.active_piece.shape[row - gs.active_piece.row][col - gs.active_piece.col]
-                        == 'a'
-                    {
-                        gs.display[row][col] =
-                            Tetrominoe::new(Some(State::Active), Some(gs.active_piece.color));
-                    }
-                }
-            }
-        }
+fn shift(gs: &mut GameState, step: i32) -> bool {
+    let piece = gs.active_piece;
+    if !fits(gs, piece.ptype, piece.row, piece.col + step, piece.rotation) {
+        return false;
+    }
+
+    gs.active_piece.col += step;
+    gs.last_rotation = None;
+    reset_lock_delay(gs);
+    true
+}
 
-        _ => (),
+fn rotate(gs: &mut GameState, rotation: Rotation) -> bool {
+    let piece = gs.active_piece;
+    let state = piece.rotated(rotation);
+
+    // try each wall kick in order and keep the first position that fits
+    for (kick, &(x, y)) in piece.kicks(rotation).iter().enumerate() {
+        let (row, col) = (piece.row - y, piece.col + x);
+        if fits(gs, piece.ptype, row, col, state) {
+            gs.active_piece = Piece {
+                rotation: state,
+                row,
+                col,
+                ..piece
+            };
+            gs.last_rotation = Some((rotation, kick));
+            reset_lock_delay(gs);
+            return true;
+        }
     }
+    false
 }
 
 pub fn new_piece(gs: &mut GameState, desired_piece: Option<char>) -> bool {
-    let half_width = gs.display[0].len() / 2;
+    let top = gs.spawn_row() as i32;
+    let piece = desired_piece.unwrap_or_else(|| gs.pop_next_piece());
+
+    // flat side down, in the rows just above the visible playfield
+    let col = Piece::spawn_col(piece, gs.board.width());
 
-    // game over
-    if gs.display[0][half_width].game_state != State::Empty {
+    // block out
+    if !fits(gs, piece, top, col, 0) {
+        gs.top_out = Some(TopOut::Block);
         return true;
     }
 
-    let piece = desired_piece.unwrap_or_else(|| get_next_piece(gs));
-    match piece {
-        'I' => {
-            // I
-            // I
-            // I
-            // I
-            gs.display[0][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Cyan));
-            gs.display[1][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Cyan));
-            gs.display[2][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Cyan));
-            gs.display[3][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Cyan));
-        }
-        'J' => {
-            //  J
-            //  J
-            // JJ
-            gs.display[0][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Blue));
-            gs.display[1][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Blue));
-            gs.display[2][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Blue));
-            gs.display[2][half_width - 1] =
-                Tetrominoe::new(Some(State::Active), Some(TColor::Blue));
-        }
-        'L' => {
-            // L
-            // L
-            // LL
-            gs.display[0][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Orange));
-            gs.display[1][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Orange));
-            gs.display[2][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Orange));
-            gs.display[2][half_width + 1] =
-                Tetrominoe::new(Some(State::Active), Some(TColor::Orange));
-        }
-        'O' => {
-            // OO
-            // OO
-            gs.display[0][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Yellow));
-            gs.display[0][half_width + 1] =
-                Tetrominoe::new(Some(State::Active), Some(TColor::Yellow));
-            gs.display[1][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Yellow));
-            gs.display[1][half_width + 1] =
-                Tetrominoe::new(Some(State::Active), Some(TColor::Yellow));
-        }
-        'S' => {
-            // SS
-            //  SS
-            gs.display[0][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Green));
-            gs.display[0][half_width + 1] =
-                Tetrominoe::new(Some(State::Active), Some(TColor::Green));
-            gs.display[1][half_width - 1] =
-                Tetrominoe::new(Some(State::Active), Some(TColor::Green));
-            gs.display[1][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Green));
-        }
-        'T' => {
-            // T
-            // TT
-            // T
-            gs.display[0][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Magenta));
-            gs.display[1][half_width - 1] =
-                Tetrominoe::new(Some(State::Active), Some(TColor::Magenta));
-            gs.display[1][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Magenta));
-            gs.display[1][half_width + 1] =
-                Tetrominoe::new(Some(State::Active), Some(TColor::Magenta));
-        }
-        'Z' => {
-            //  ZZ
-            // ZZ
-            gs.display[0][half_width - 1] = Tetrominoe::new(Some(State::Active), Some(TColor::Red));
-            gs.display[0][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Red));
-            gs.display[1][half_width] = Tetrominoe::new(Some(State::Active), Some(TColor::Red));
-            gs.display[1][half_width + 1] = Tetrominoe::new(Some(State::Active), Some(TColor::Red));
-        }
-        _ => panic!("unknown picece: {}", piece),
+    gs.active_piece = Piece::new(piece, top, col);
+    gs.lock_timer = Duration::ZERO;
+    gs.lock_resets = 0;
+    gs.lowest_row = top;
+    gs.last_rotation = None;
+
+    // drop straight into view when there is room, instead of waiting out a whole
+    // gravity interval in the hidden rows
+    if hidden(gs) {
+        gravity(gs);
     }
-    gs.active_piece.set(piece);
-    gs.active_piece.set_pos(0, half_width - 1);
     false
 }
 
 pub fn landed(gs: &mut GameState) {
-    for row in &mut gs.display {
-        for ch in row {
-            if ch.game_state == State::Active {
-                ch.game_state = State::Landed;
-            }
-        }
+    let piece = gs.active_piece;
+    for (row, col) in piece.cells() {
+        gs.board.set(row as usize, col as usize, piece.color());
     }
 }
 
-pub fn full_line(gs: &mut GameState) {
-    let mut lines: usize = 0;
-    'outer: for row in (0..gs.display.len()).rev() {
-        for ch in &gs.display[row] {
-            if ch.game_state != State::Landed {
-                continue 'outer;
-            }
-        }
-        gs.display.remove(row);
-        lines += 1;
+pub fn full_line(gs: &mut GameState) -> usize {
+    gs.board.clear_lines()
+}
+
+pub fn board_empty(gs: &GameState) -> bool {
+    gs.board.is_empty()
+}
+
+// how many rows the active piece can fall before it lands
+pub fn drop_distance(gs: &GameState) -> i32 {
+    let piece = gs.active_piece;
+    let mut rows = 0;
+    while fits(
+        gs,
+        piece.ptype,
+        piece.row + rows + 1,
+        piece.col,
+        piece.rotation,
+    ) {
+        rows += 1;
     }
+    rows
+}
 
-    for _ in 0..lines {
-        gs.display
-            .insert(0, vec![Tetrominoe::default(); gs.display[0].len()]); // add new line at the top
+// where the active piece would land
+pub fn ghost_piece(gs: &GameState) -> Piece {
+    Piece {
+        row: gs.active_piece.row + drop_distance(gs),
+        ..gs.active_piece
     }
+}
 
-    match lines {
-        1 => gs.gamescore.score += 40 * (gs.gamescore.level + 1),
-        2 => gs.gamescore.score += 100 * (gs.gamescore.level + 1),
-        3 => gs.gamescore.score += 300 * (gs.gamescore.level + 1),
-        4 => gs.gamescore.score += 1200 * (gs.gamescore.level + 1),
-        _ => (),
+fn hold(gs: &mut GameState) -> bool {
+    // only one hold per piece, until it locks
+    if gs.hold_used {
+        return false;
     }
 
-    gs.gamescore.level = gs.gamescore.score / 1000;
+    // hold piece in its spawn rotation
+    let prev_piece = Tetrominoe::from(gs.active_piece.ptype);
+    if let Some(hold) = gs.hold_piece {
+        new_piece(gs, Some(hold.ptype));
+    } else {
+        new_piece(gs, None);
+    }
+    gs.hold_piece = Some(prev_piece);
+    gs.hold_used = true;
+    true
 }
 
-pub fn ghost_piece(gs: &mut GameState) {
-    for row in 0..gs.display.len() {
-        for col in 0..gs.display[row].len() {
-            if gs.display[row][col].game_state == State::Ghost {
-                gs.display[row][col].game_state = State::Empty;
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameconfig::GameMode;
+
+    // an empty 10 wide board, 20 visible rows under 20 hidden ones, with the given
+    // piece active
+    fn game(piece: Piece) -> GameState {
+        let mut gs = GameState::new(10, 20, 0);
+        gs.active_piece = piece;
+        gs
+    }
+
+    fn fill(gs: &mut GameState, cells: &[(usize, usize)]) {
+        for &(row, col) in cells {
+            gs.board.set(row, col, TColor::Gray);
         }
     }
 
-    let mut ghost = gs.clone();
+    #[test]
+    fn t_kicks_off_the_left_wall() {
+        // pointing right with its stem against the wall, the flat side would poke out
+        let mut gs = game(Piece {
+            ptype: 'T',
+            rotation: 1,
+            row: 30,
+            col: -1,
+        });
+
+        let result = handle_input(&mut gs, Action::RotateCW);
+        assert!(result.success);
+        assert_eq!(gs.active_piece.rotation, 2);
+        assert_eq!((gs.active_piece.row, gs.active_piece.col), (30, 0));
+        assert_eq!(gs.last_rotation, Some((Rotation::Clockwise, 1)));
+    }
+
+    #[test]
+    fn t_kick_moves_up_for_positive_y() {
+        // tests 0 and 1 are blocked below, test 2 is (-1, +1): one left and one row up
+        let mut gs = game(Piece {
+            ptype: 'T',
+            rotation: 0,
+            row: 30,
+            col: 4,
+        });
+        fill(&mut gs, &[(32, 4), (32, 5)]);
+
+        assert!(handle_input(&mut gs, Action::RotateCW).success);
+        assert_eq!(gs.active_piece.rotation, 1);
+        assert_eq!((gs.active_piece.row, gs.active_piece.col), (29, 3));
+        assert_eq!(gs.last_rotation, Some((Rotation::Clockwise, 2)));
+    }
+
+    #[test]
+    fn i_kicks_off_the_left_wall() {
+        // vertical against the wall, flat it needs the I table's (+2, 0)
+        let mut gs = game(Piece {
+            ptype: 'I',
+            rotation: 1,
+            row: 30,
+            col: -2,
+        });
+
+        assert!(handle_input(&mut gs, Action::RotateCW).success);
+        assert_eq!(gs.active_piece.rotation, 2);
+        assert_eq!((gs.active_piece.row, gs.active_piece.col), (30, 0));
+        assert_eq!(gs.last_rotation, Some((Rotation::Clockwise, 2)));
+    }
+
+    #[test]
+    fn rotation_fails_when_no_kick_fits() {
+        let mut gs = game(Piece {
+            ptype: 'T',
+            rotation: 0,
+            row: 37,
+            col: 0,
+        });
+        // box the T into its spawn cells
+        let cells: Vec<(usize, usize)> = (35..40)
+            .flat_map(|row| (0..10).map(move |col| (row, col)))
+            .filter(|&(row, col)| !matches!((row, col), (37, 1) | (38, 0) | (38, 1) | (38, 2)))
+            .collect();
+        fill(&mut gs, &cells);
+
+        let result = handle_input(&mut gs, Action::RotateCW);
+        assert!(!result.success);
+        assert_eq!(gs.active_piece.rotation, 0);
+        assert_eq!((gs.active_piece.row, gs.active_piece.col), (37, 0));
+    }
+
+    #[test]
+    fn garbage_out_when_the_top_row_is_pushed_off() {
+        let mut gs = game(Piece::new('O', 30, 3));
+        assert!(!add_garbage(&mut gs, 0));
+        assert!(!gs.is_game_over());
+
+        fill(&mut gs, &[(0, 5)]);
+        assert!(add_garbage(&mut gs, 0));
+        assert_eq!(gs.top_out, Some(TopOut::Garbage));
+    }
+
+    #[test]
+    fn garbage_pushes_the_active_piece_up() {
+        let mut gs = game(Piece::new('O', 38, 3));
+        assert!(!add_garbage(&mut gs, 0));
+        assert_eq!(gs.active_piece.row, 37);
+        assert!(grounded(&gs));
+
+        // nothing to push it up into
+        let mut gs = game(Piece::new('O', 0, 3));
+        fill(&mut gs, &[(2, 4)]);
+        assert!(add_garbage(&mut gs, 0));
+        assert_eq!(gs.top_out, Some(TopOut::Garbage));
+    }
 
-    gravity_until_new_piece(&mut ghost);
+    #[test]
+    fn hard_drop_reports_lock_out() {
+        // resting on a stack that reaches the top of the visible playfield
+        let mut gs = game(Piece::new('O', 18, 3));
+        let hidden_rows = gs.config.hidden_rows;
+        for row in hidden_rows..gs.board.height() {
+            fill(&mut gs, &(0..9).map(|col| (row, col)).collect::<Vec<_>>());
+        }
+
+        let result = handle_input(&mut gs, Action::HardDrop);
+        assert!(result.locked);
+        assert!(result.game_over);
+        assert_eq!(gs.top_out, Some(TopOut::Lock));
+
+        let result = handle_input(&mut gs, Action::HardDrop);
+        assert!(!result.success);
+        assert!(result.game_over);
+    }
 
-    for row in 0..ghost.display.len() {
-        for col in 0..ghost.display[row].len() {
-            if ghost.display[row][col].game_state == State::Active
-                && gs.display[row][col].game_state == State::Empty
-            {
-                gs.display[row][col].game_state = State::Ghost;
+    // rows of '#' and '.' ending at the floor
+    fn stack(gs: &mut GameState, rows: &[&str]) {
+        let top = gs.board.height() - rows.len();
+        for (row, cells) in rows.iter().enumerate() {
+            for (col, cell) in cells.chars().enumerate() {
+                if cell == '#' {
+                    gs.board.set(top + row, col, TColor::Gray);
+                }
             }
         }
     }
-}
 
-fn gravity_until_new_piece(gs: &mut GameState) {
-    let mut prev_display = gs.display.clone();
-    gravity(gs);
-    while gs.display[0][gs.display[0].len() / 2].game_state == State::Empty {
-        prev_display = gs.display.clone();
-        gravity(gs);
+    fn last_spin(gs: &mut GameState) -> (usize, Spin) {
+        handle_input(gs, Action::HardDrop);
+        let clear = gs.gamescore.last_clear.expect("nothing to announce");
+        (clear.lines, clear.spin)
+    }
+
+    #[test]
+    fn t_spin_double_slot() {
+        let mut gs = game(Piece {
+            ptype: 'T',
+            rotation: 1,
+            row: 37,
+            col: 3,
+        });
+        stack(
+            &mut gs,
+            &[
+                "...#......", // overhang
+                "###...####",
+                "####.#####",
+            ],
+        );
+
+        assert!(handle_input(&mut gs, Action::RotateCW).success);
+        assert_eq!(gs.last_rotation, Some((Rotation::Clockwise, 0)));
+        assert_eq!(last_spin(&mut gs), (2, Spin::Full));
+    }
+
+    #[test]
+    fn t_spin_mini_off_the_wall() {
+        let mut gs = game(Piece {
+            ptype: 'T',
+            rotation: 0,
+            row: 37,
+            col: 0,
+        });
+        stack(&mut gs, &[".#########"]);
+
+        // only one of the corners it points at is filled, the other two are the wall
+        assert!(handle_input(&mut gs, Action::RotateCW).success);
+        assert_eq!(gs.last_rotation, Some((Rotation::Clockwise, 1)));
+        assert_eq!(last_spin(&mut gs), (1, Spin::Mini));
     }
-    gs.display = prev_display;
-}
 
-pub fn hold(gs: &mut GameState) {
-    // clear piece
-    for row in gs.display.iter_mut() {
-        for col in row.iter_mut() {
-            if col.game_state == State::Active {
-                col.game_state = State::Empty;
+    #[test]
+    fn last_kick_upgrades_mini_to_full() {
+        let mut gs = game(Piece {
+            ptype: 'T',
+            rotation: 2,
+            row: 34,
+            col: 0,
+        });
+        stack(
+            &mut gs,
+            &[
+                "##.#.#####",
+                "....####.#",
+                "...##.##.#",
+                ".....##...",
+                ".###..#..#",
+                "#....#.##.",
+            ],
+        );
+
+        // the corners alone make this a mini, kicking two rows down with (-1, -2) makes it full
+        assert!(handle_input(&mut gs, Action::RotateCCW).success);
+        assert_eq!(gs.last_rotation, Some((Rotation::CounterClockwise, 4)));
+        assert_eq!((gs.active_piece.row, gs.active_piece.col), (36, -1));
+        assert_eq!(last_spin(&mut gs), (0, Spin::Full));
+    }
+
+    #[test]
+    fn falling_after_rotating_is_not_a_spin() {
+        let mut gs = game(Piece {
+            ptype: 'T',
+            rotation: 1,
+            row: 36,
+            col: -1,
+        });
+        stack(&mut gs, &[".#########"]);
+        gs.last_rotation = Some((Rotation::Clockwise, 1));
+
+        assert!(handle_input(&mut gs, Action::SoftDrop).success);
+        assert_eq!(gs.last_rotation, None);
+        assert_eq!(last_spin(&mut gs), (1, Spin::None));
+    }
+
+    #[test]
+    fn lock_delay_locks_after_the_delay() {
+        let mut gs = game(Piece::new('O', 38, 3));
+        assert!(grounded(&gs));
+        assert!(!lock_delay(&mut gs, Duration::from_millis(300)));
+        assert_eq!(gs.counter, 0);
+        assert!(!lock_delay(&mut gs, Duration::from_millis(300)));
+        assert_eq!(gs.counter, 1);
+    }
+
+    #[test]
+    fn moving_resets_the_lock_delay_until_the_limit() {
+        let mut gs = game(Piece::new('O', 38, 3));
+        for _ in 0..gs.config.lock_resets {
+            lock_delay(&mut gs, Duration::from_millis(300));
+            let step = if gs.active_piece.col == 3 { 1 } else { -1 };
+            assert!(shift(&mut gs, step));
+        }
+        assert_eq!(gs.counter, 0);
+
+        lock_delay(&mut gs, Duration::from_millis(300));
+        assert!(shift(&mut gs, 1));
+        lock_delay(&mut gs, Duration::from_millis(300));
+        assert_eq!(gs.counter, 1);
+    }
+
+    #[test]
+    fn lifting_off_the_floor_does_not_refill_spent_resets() {
+        // turning a flat T kicks it up a row, and it lands back on the same row
+        let mut gs = game(Piece::new('T', 38, 3));
+        for _ in 0..100 {
+            lock_delay(&mut gs, Duration::from_millis(100));
+            handle_input(&mut gs, Action::RotateCW);
+            lock_delay(&mut gs, Duration::from_millis(100));
+            handle_input(&mut gs, Action::RotateCCW);
+            lock_delay(&mut gs, Duration::from_millis(100));
+            handle_input(&mut gs, Action::SoftDrop);
+            if gs.counter > 0 {
+                break;
             }
         }
+        assert_eq!(gs.counter, 1);
     }
 
-    // hold piece
-    if let Some(hold) = &gs.hold_piece {
-        let prev_piece = gs.active_piece;
-        new_piece(gs, Some(hold.ptype));
-        gs.hold_piece = Some(prev_piece);
-    } else {
-        gs.hold_piece = Some(gs.active_piece);
-        new_piece(gs, None);
+    #[test]
+    fn a_new_lowest_row_gives_back_the_lock_delay() {
+        let mut gs = game(Piece::new('O', 36, 3));
+        stack(&mut gs, &["....##....", "....##...."]);
+        gs.lowest_row = 36;
+        gs.lock_resets = gs.config.lock_resets;
+        lock_delay(&mut gs, Duration::from_millis(400));
+
+        // off the ledge and down to the floor
+        assert!(shift(&mut gs, 2));
+        assert!(gravity(&mut gs));
+        assert_eq!(gs.lock_resets, 0);
+        assert_eq!(gs.lock_timer, Duration::ZERO);
     }
-}
 
-fn get_next_piece(gs: &mut GameState) -> char {
-    let temp = gs.next_piece.ptype;
-    gs.next_piece = Tetrominoe::random(&mut gs.bag);
-    temp
+    #[test]
+    fn spawned_pieces_drop_into_view() {
+        let gs = GameState::new(10, 20, 0);
+        assert_eq!(gs.active_piece.row, gs.spawn_row() as i32 + 1);
+        assert!(!hidden(&gs));
+
+        // without hidden rows they are already in view
+        let gs = GameState::with_config(10, 20, 0, GameMode::Classic.config());
+        assert_eq!(gs.active_piece.row, 0);
+    }
 }
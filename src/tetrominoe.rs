@@ -1,4 +1,4 @@
-use crate::{bag::Bag, tetlib::EMP};
+use crate::randomizer::Randomizer;
 
 #[derive(Clone, PartialEq, Debug, Copy, Default)]
 pub enum TColor {
@@ -9,258 +9,297 @@ pub enum TColor {
     Blue,
     Magenta,
     Orange,
+    Gray,
     #[default]
     Empty,
 }
 
-#[derive(Clone, PartialEq, Debug, Copy, Default)]
-pub enum State {
-    Landed,
-    Active,
-    Ghost,
-    #[default]
-    Empty,
+impl TColor {
+    // block sprite for cells of this color
+    pub fn texture(&self) -> &'static str {
+        match self {
+            TColor::Cyan => "blocks/cyan.png",
+            TColor::Blue => "blocks/blue.png",
+            TColor::Orange => "blocks/orange.png",
+            TColor::Yellow => "blocks/yellow.png",
+            TColor::Red => "blocks/red.png",
+            TColor::Magenta => "blocks/magenta.png",
+            TColor::Green => "blocks/green.png",
+            TColor::Gray => "blocks/gray.png",
+            TColor::Empty => "",
+        }
+    }
 }
 
-#[derive(Clone, PartialEq, Debug, Copy, Default)]
-pub struct Tetrominoe {
-    pub shape: [[char; 4]; 4],
-    pub row: usize,
-    pub col: usize,
-    pub ptype: char,
-    pub color: TColor,
-    pub game_state: State,
-    rotation_state: usize,
+#[derive(Clone, PartialEq, Debug, Copy)]
+pub enum Rotation {
+    Clockwise,
+    CounterClockwise,
+    Half,
 }
 
-impl Tetrominoe {
-    pub fn new(state: Option<State>, color: Option<TColor>) -> Tetrominoe {
-        Tetrominoe {
-            shape: [[EMP; 4]; 4],
-            row: 0,
-            col: 0,
-            ptype: ' ',
-            color: color.unwrap_or(TColor::Empty),
-            game_state: state.unwrap_or(State::Empty),
-            rotation_state: 0,
-        }
-    }
+// SRS wall kicks, indexed by the rotation state being left (0, R, 2, L)
+const JLSTZ_KICKS: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+];
 
-    pub fn set(&mut self, shape: char) -> &mut Self {
-        self.ptype = shape;
-        let shape = match shape {
-            'I' => {
-                self.color = TColor::Cyan;
-                [
-                    [EMP, 'a', EMP, EMP],
-                    [EMP, 'a', EMP, EMP],
-                    [EMP, 'a', EMP, EMP],
-                    [EMP, 'a', EMP, EMP],
-                ]
-            }
+const I_KICKS: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+];
 
-            'J' => {
-                self.color = TColor::Blue;
-                [
-                    [EMP, 'a', EMP, EMP],
-                    [EMP, 'a', EMP, EMP],
-                    ['a', 'a', EMP, EMP],
-                    [EMP, EMP, EMP, EMP],
-                ]
-            }
+const JLSTZ_CCW_KICKS: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+];
 
-            'L' => {
-                self.color = TColor::Orange;
-                [
-                    [EMP, 'a', EMP, EMP],
-                    [EMP, 'a', EMP, EMP],
-                    [EMP, 'a', 'a', EMP],
-                    [EMP, EMP, EMP, EMP],
-                ]
-            }
+const I_CCW_KICKS: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+];
 
-            'O' => {
-                self.color = TColor::Yellow;
-                [
-                    [EMP, 'a', 'a', EMP],
-                    [EMP, 'a', 'a', EMP],
-                    [EMP, EMP, EMP, EMP],
-                    [EMP, EMP, EMP, EMP],
-                ]
-            }
+// 180 degree kicks are not part of SRS, these follow the common SRS+ table
+const HALF_TURN_KICKS: [[(i32, i32); 6]; 4] = [
+    [(0, 0), (0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0)],
+    [(0, 0), (1, 0), (1, 2), (1, 1), (0, 2), (0, 1)],
+    [(0, 0), (0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)],
+    [(0, 0), (-1, 0), (-1, 2), (-1, 1), (0, 2), (0, 1)],
+];
 
-            'Z' => {
-                self.color = TColor::Red;
-                [
-                    [EMP, EMP, EMP, EMP],
-                    ['a', 'a', EMP, EMP],
-                    [EMP, 'a', 'a', EMP],
-                    [EMP, EMP, EMP, EMP],
-                ]
-            }
+// filled cells of every piece in rotation states 0, R, 2 and L, relative to the top left
+// of its box, in the order of PIECES. J, L, S, T and Z turn inside a 3x3 box, I inside
+// a 4x4 one and O not at all
+const SHAPES: [[[(i32, i32); 4]; 4]; 7] = [
+    // I
+    [
+        [(1, 0), (1, 1), (1, 2), (1, 3)],
+        [(0, 2), (1, 2), (2, 2), (3, 2)],
+        [(2, 0), (2, 1), (2, 2), (2, 3)],
+        [(0, 1), (1, 1), (2, 1), (3, 1)],
+    ],
+    // J
+    [
+        [(0, 0), (1, 0), (1, 1), (1, 2)],
+        [(0, 1), (0, 2), (1, 1), (2, 1)],
+        [(1, 0), (1, 1), (1, 2), (2, 2)],
+        [(0, 1), (1, 1), (2, 0), (2, 1)],
+    ],
+    // L
+    [
+        [(0, 2), (1, 0), (1, 1), (1, 2)],
+        [(0, 1), (1, 1), (2, 1), (2, 2)],
+        [(1, 0), (1, 1), (1, 2), (2, 0)],
+        [(0, 0), (0, 1), (1, 1), (2, 1)],
+    ],
+    // O
+    [
+        [(0, 1), (0, 2), (1, 1), (1, 2)],
+        [(0, 1), (0, 2), (1, 1), (1, 2)],
+        [(0, 1), (0, 2), (1, 1), (1, 2)],
+        [(0, 1), (0, 2), (1, 1), (1, 2)],
+    ],
+    // S
+    [
+        [(0, 1), (0, 2), (1, 0), (1, 1)],
+        [(0, 1), (1, 1), (1, 2), (2, 2)],
+        [(1, 1), (1, 2), (2, 0), (2, 1)],
+        [(0, 0), (1, 0), (1, 1), (2, 1)],
+    ],
+    // T
+    [
+        [(0, 1), (1, 0), (1, 1), (1, 2)],
+        [(0, 1), (1, 1), (1, 2), (2, 1)],
+        [(1, 0), (1, 1), (1, 2), (2, 1)],
+        [(0, 1), (1, 0), (1, 1), (2, 1)],
+    ],
+    // Z
+    [
+        [(0, 0), (0, 1), (1, 1), (1, 2)],
+        [(0, 2), (1, 1), (1, 2), (2, 1)],
+        [(1, 0), (1, 1), (2, 1), (2, 2)],
+        [(0, 1), (1, 0), (1, 1), (2, 0)],
+    ],
+];
 
-            'T' => {
-                self.color = TColor::Magenta;
-                [
-                    [EMP, 'a', EMP, EMP],
-                    ['a', 'a', 'a', EMP],
-                    [EMP, EMP, EMP, EMP],
-                    [EMP, EMP, EMP, EMP],
-                ]
+// the same cells as one bitmask per row of the box, bit n for column n
+const MASKS: [[[u16; 4]; 4]; 7] = {
+    let mut masks = [[[0; 4]; 4]; 7];
+    let mut piece = 0;
+    while piece < 7 {
+        let mut rotation = 0;
+        while rotation < 4 {
+            let mut cell = 0;
+            while cell < 4 {
+                let (row, col) = SHAPES[piece][rotation][cell];
+                masks[piece][rotation][row as usize] |= 1 << col;
+                cell += 1;
             }
+            rotation += 1;
+        }
+        piece += 1;
+    }
+    masks
+};
 
-            'S' => {
-                self.color = TColor::Green;
-                [
-                    [EMP, EMP, EMP, EMP],
-                    [EMP, 'a', 'a', EMP],
-                    ['a', 'a', EMP, EMP],
-                    [EMP, EMP, EMP, EMP],
-                ]
-            }
+const COLORS: [TColor; 7] = [
+    TColor::Cyan,
+    TColor::Blue,
+    TColor::Orange,
+    TColor::Yellow,
+    TColor::Green,
+    TColor::Magenta,
+    TColor::Red,
+];
 
-            _ => panic!("Unknown shape: {}", shape),
-        };
-        self.shape = shape;
-        self.rotation_state = 0;
-        self
+// index into the tables above
+fn index(ptype: char) -> usize {
+    match ptype {
+        'I' => 0,
+        'J' => 1,
+        'L' => 2,
+        'O' => 3,
+        'S' => 4,
+        'T' => 5,
+        'Z' => 6,
+        _ => panic!("Unknown shape: {}", ptype),
     }
+}
 
-    pub fn set_pos(&mut self, row: usize, col: usize) {
-        self.row = row;
-        self.col = col;
-    }
+// a piece type waiting in the queue or the hold, its cells come from SHAPES
+#[derive(Clone, PartialEq, Debug, Copy, Default)]
+pub struct Tetrominoe {
+    pub ptype: char,
+    pub color: TColor,
+}
 
-    pub fn rotate(&mut self) {
-        match self.ptype {
-            'O' => (),
-            'I' | 'J' | 'L' => {
-                // transpose or swap rows and columns
-                let n = self.shape.len();
-                for i in 0..n {
-                    for j in i..n {
-                        let temp = self.shape[i][j];
-                        self.shape[i][j] = self.shape[j][i];
-                        self.shape[j][i] = temp;
-                    }
-                }
+impl Tetrominoe {
+    pub fn from(ptype: char) -> Tetrominoe {
+        Tetrominoe {
+            ptype,
+            color: COLORS[index(ptype)],
+        }
+    }
 
-                // reverse each row to rotate
-                for i in 0..n {
-                    self.shape[i].reverse();
-                }
-            }
+    pub fn random(randomizer: &mut dyn Randomizer) -> Tetrominoe {
+        let piece = randomizer.draw();
+        Tetrominoe::from(piece)
+    }
+}
 
-            'Z' => {
-                if self.rotation_state == 0 {
-                    self.shape = [
-                        [EMP, EMP, EMP, EMP],
-                        [EMP, EMP, 'a', EMP],
-                        [EMP, 'a', 'a', EMP],
-                        [EMP, 'a', EMP, EMP],
-                    ];
-                    self.rotation_state = 1;
-                } else {
-                    self.shape = [
-                        [EMP, EMP, EMP, EMP],
-                        ['a', 'a', EMP, EMP],
-                        [EMP, 'a', 'a', EMP],
-                        [EMP, EMP, EMP, EMP],
-                    ];
-                    self.rotation_state = 0;
-                }
-            }
+// the falling piece, its cells and color are derived from its type and rotation state
+#[derive(Clone, PartialEq, Debug, Copy)]
+pub struct Piece {
+    pub ptype: char,
+    // 0 for spawn, then R, 2 and L
+    pub rotation: usize,
+    pub row: i32,
+    pub col: i32,
+}
 
-            'S' => {
-                if self.rotation_state == 0 {
-                    self.shape = [
-                        [EMP, EMP, EMP, EMP],
-                        [EMP, 'a', EMP, EMP],
-                        [EMP, 'a', 'a', EMP],
-                        [EMP, EMP, 'a', EMP],
-                    ];
+impl Piece {
+    pub fn new(ptype: char, row: i32, col: i32) -> Piece {
+        Piece {
+            ptype,
+            rotation: 0,
+            row,
+            col,
+        }
+    }
 
-                    self.rotation_state = 1;
-                } else {
-                    self.shape = [
-                        [EMP, EMP, EMP, EMP],
-                        [EMP, 'a', 'a', EMP],
-                        ['a', 'a', EMP, EMP],
-                        [EMP, EMP, EMP, EMP],
-                    ];
-                    self.rotation_state = 0;
-                }
-            }
+    // filled cells of a piece type in a rotation state, relative to the top left of its box
+    pub fn shape(ptype: char, rotation: usize) -> [(i32, i32); 4] {
+        SHAPES[index(ptype)][rotation]
+    }
 
-            'T' => {
-                self.shape = match self.rotation_state {
-                    0 => {
-                        self.rotation_state += 1;
-                        [
-                            [EMP, 'a', EMP, EMP],
-                            [EMP, 'a', 'a', EMP],
-                            [EMP, 'a', EMP, EMP],
-                            [EMP, EMP, EMP, EMP],
-                        ]
-                    }
+    // rows of a piece type in a rotation state as bitmasks, bit n for column n of its box
+    pub fn masks(ptype: char, rotation: usize) -> [u16; 4] {
+        MASKS[index(ptype)][rotation]
+    }
 
-                    1 => {
-                        self.rotation_state += 1;
-                        [
-                            [EMP, EMP, EMP, EMP],
-                            ['a', 'a', 'a', EMP],
-                            [EMP, 'a', EMP, EMP],
-                            [EMP, EMP, EMP, EMP],
-                        ]
-                    }
+    // board positions of the piece's cells
+    pub fn cells(&self) -> [(i32, i32); 4] {
+        Piece::shape(self.ptype, self.rotation).map(|(row, col)| (self.row + row, self.col + col))
+    }
 
-                    2 => {
-                        self.rotation_state += 1;
-                        [
-                            [EMP, 'a', EMP, EMP],
-                            ['a', 'a', EMP, EMP],
-                            [EMP, 'a', EMP, EMP],
-                            [EMP, EMP, EMP, EMP],
-                        ]
-                    }
+    pub fn color(&self) -> TColor {
+        COLORS[index(self.ptype)]
+    }
 
-                    3 => {
-                        self.rotation_state = 0;
-                        [
-                            [EMP, 'a', EMP, EMP],
-                            ['a', 'a', 'a', EMP],
-                            [EMP, EMP, EMP, EMP],
-                            [EMP, EMP, EMP, EMP],
-                        ]
-                    }
+    // leftmost column of the piece's box that centers its cells on a board this wide,
+    // leaning left when they cannot be centered exactly
+    pub fn spawn_col(ptype: char, width: usize) -> i32 {
+        let shape = Piece::shape(ptype, 0);
+        let first = shape.iter().map(|&(_, col)| col).min().unwrap();
+        let last = shape.iter().map(|&(_, col)| col).max().unwrap();
+        (width as i32 - (last - first + 1)) / 2 - first
+    }
 
-                    _ => panic!("Unknown rotation state: {}", self.rotation_state),
-                }
-            }
+    // rotation state after turning the piece
+    pub fn rotated(&self, rotation: Rotation) -> usize {
+        let turns = match rotation {
+            Rotation::Clockwise => 1,
+            Rotation::Half => 2,
+            Rotation::CounterClockwise => 3,
+        };
+        (self.rotation + turns) % 4
+    }
 
-            _ => panic!("Unknown shape: {}", self.ptype),
+    // offsets (x right, y up) to try in order when rotating out of the current state
+    pub fn kicks(&self, rotation: Rotation) -> &'static [(i32, i32)] {
+        match (self.ptype, rotation) {
+            ('O', _) => &[(0, 0)],
+            (_, Rotation::Half) => &HALF_TURN_KICKS[self.rotation],
+            ('I', Rotation::Clockwise) => &I_KICKS[self.rotation],
+            ('I', Rotation::CounterClockwise) => &I_CCW_KICKS[self.rotation],
+            (_, Rotation::Clockwise) => &JLSTZ_KICKS[self.rotation],
+            (_, Rotation::CounterClockwise) => &JLSTZ_CCW_KICKS[self.rotation],
         }
     }
+}
 
-    pub fn from(ptype: char, state: Option<State>) -> Tetrominoe {
-        *Tetrominoe::new(state, None).set(ptype)
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::randomizer::PIECES;
 
-    pub fn random(bag: &mut Bag) -> Tetrominoe {
-        let piece = bag.draw();
-        Tetrominoe::from(piece, None)
+    #[test]
+    fn each_state_is_the_last_turned_clockwise() {
+        for ptype in PIECES {
+            let n = match ptype {
+                'O' => continue,
+                'I' => 4,
+                _ => 3,
+            };
+            for rotation in 0..4 {
+                let mut turned = Piece::shape(ptype, rotation).map(|(row, col)| (col, n - 1 - row));
+                turned.sort();
+                assert_eq!(turned, Piece::shape(ptype, (rotation + 1) % 4), "{}", ptype);
+            }
+        }
     }
 
-    pub fn as_color(&self) -> &str {
-        match self.color {
-            TColor::Cyan => "blocks/cyan.png",
-            TColor::Blue => "blocks/blue.png",
-            TColor::Orange => "blocks/orange.png",
-            TColor::Yellow => "blocks/yellow.png",
-            TColor::Red => "blocks/red.png",
-            TColor::Magenta => "blocks/magenta.png",
-            TColor::Green => "blocks/green.png",
-            TColor::Empty => "",
+    #[test]
+    fn masks_match_shapes() {
+        for ptype in PIECES {
+            for rotation in 0..4 {
+                let masks = Piece::masks(ptype, rotation);
+                for row in 0..4 {
+                    for col in 0..4 {
+                        let filled = masks[row as usize] & (1 << col) != 0;
+                        let cell = Piece::shape(ptype, rotation).contains(&(row, col));
+                        assert_eq!(filled, cell);
+                    }
+                }
+            }
         }
     }
 }